@@ -0,0 +1,283 @@
+use std::{
+    cmp::Reverse,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use image::RgbaImage;
+use jiff::{Timestamp, civil::Date, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::{entry_id, jobs::JobId};
+
+/// Metadata stored in a JSON sidecar file next to each archived image.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub job: Option<JobId>,
+    pub document: String,
+    /// The data the document was rendered with.
+    pub params: serde_json::Value,
+    pub submitter: Option<String>,
+    pub printer: String,
+    pub printed: Timestamp,
+    pub width: u32,
+    pub height: u32,
+    /// Length of paper used, assuming the usual 203 dpi.
+    pub length_mm: f32,
+}
+
+impl Record {
+    /// Whether any of the record's text contains the query, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let params = self.params.to_string();
+        [Some(&self.document), self.submitter.as_ref(), Some(&params)]
+            .into_iter()
+            .flatten()
+            .any(|it| it.to_lowercase().contains(&query))
+    }
+}
+
+pub struct Entry {
+    pub id: String,
+    pub record: Record,
+}
+
+/// Filters for [`Archive::entries`]. Empty filters match every entry.
+#[derive(Default)]
+pub struct Filter {
+    pub printer: Option<String>,
+    pub document: Option<String>,
+    /// Text to search for, see [`Record::matches`].
+    pub query: Option<String>,
+    /// First day to include (UTC).
+    pub from: Option<Date>,
+    /// Last day to include (UTC).
+    pub to: Option<Date>,
+}
+
+impl Filter {
+    fn matches(&self, record: &Record) -> bool {
+        self.printer.as_ref().is_none_or(|it| *it == record.printer)
+            && self
+                .document
+                .as_ref()
+                .is_none_or(|it| *it == record.document)
+            && self.query.as_ref().is_none_or(|it| record.matches(it))
+    }
+
+    fn matches_day(&self, day: Date) -> bool {
+        self.from.is_none_or(|it| it <= day) && self.to.is_none_or(|it| day <= it)
+    }
+}
+
+/// A directory containing every image that was printed.
+///
+/// Images are stored at `{year}/{month}/{day}/{id}.png` (in UTC) next to a
+/// `{id}.json` sidecar file containing their [`Record`]. Ids consist of the
+/// time of printing in milliseconds and the printer's name, so the directory
+/// can be derived from the id.
+#[derive(Clone)]
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn day_of(timestamp: Timestamp) -> Date {
+        timestamp.to_zoned(TimeZone::UTC).date()
+    }
+
+    fn day_dir(&self, day: Date) -> PathBuf {
+        self.dir
+            .join(format!("{:04}", day.year()))
+            .join(format!("{:02}", day.month()))
+            .join(format!("{:02}", day.day()))
+    }
+
+    fn entry_dir(&self, id: &str) -> anyhow::Result<PathBuf> {
+        entry_id::check(id)?;
+        let Some(millis) = id.split('-').next().and_then(|it| it.parse().ok()) else {
+            bail!("invalid archive entry id {id:?}");
+        };
+        let timestamp = Timestamp::from_millisecond(millis)?;
+        Ok(self.day_dir(Self::day_of(timestamp)))
+    }
+
+    fn image_path(&self, id: &str) -> anyhow::Result<PathBuf> {
+        Ok(self.entry_dir(id)?.join(format!("{id}.png")))
+    }
+
+    fn meta_path(&self, id: &str) -> anyhow::Result<PathBuf> {
+        Ok(self.entry_dir(id)?.join(format!("{id}.json")))
+    }
+
+    pub fn push(&self, image: &RgbaImage, record: &Record) -> anyhow::Result<String> {
+        // Several images may be printed within the same millisecond, e.g. text
+        // that was printed natively. Each printer archives one image at a
+        // time, so a counter is enough to tell them apart.
+        let base = format!("{}-{}", record.printed.as_millisecond(), record.printer);
+        let mut id = base.clone();
+        for n in 1.. {
+            if !self.image_path(&id)?.exists() {
+                break;
+            }
+            id = format!("{base}-{n}");
+        }
+        let dir = self.entry_dir(&id)?;
+        println!("Archiving image {id}");
+
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("At {}", dir.display()))
+            .context("Failed to create archive directory")?;
+
+        let path = self.image_path(&id)?;
+        image
+            .save(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to save image to archive")?;
+
+        let path = self.meta_path(&id)?;
+        let json = serde_json::to_vec_pretty(record).expect("record should serialize to json");
+        fs::write(&path, json)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to write archive metadata")?;
+
+        Ok(id)
+    }
+
+    /// Subdirectories whose names are numbers, sorted descending.
+    fn numbered_subdirs(dir: &Path) -> anyhow::Result<Vec<(i16, PathBuf)>> {
+        let mut subdirs = vec![];
+        match dir.read_dir() {
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+
+            Err(err) => Err(err)
+                .with_context(|| format!("At {}", dir.display()))
+                .context("Failed to open archive dir")?,
+
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+                    if !entry.file_type()?.is_dir() {
+                        continue;
+                    }
+                    let Some(number) = entry.file_name().to_str().and_then(|it| it.parse().ok())
+                    else {
+                        continue;
+                    };
+                    subdirs.push((number, entry.path()));
+                }
+            }
+        }
+        subdirs.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(subdirs)
+    }
+
+    fn entries_of_day(dir: &Path) -> anyhow::Result<Vec<Entry>> {
+        let mut entries = vec![];
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|it| it != "json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|it| it.to_str()) else {
+                continue;
+            };
+
+            let record = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|it| Ok(serde_json::from_slice(&it)?));
+            match record {
+                Ok(record) => entries.push(Entry {
+                    id: id.to_string(),
+                    record,
+                }),
+                Err(err) => println!("Failed to read {}: {err}", path.display()),
+            }
+        }
+        entries.sort_unstable_by_key(|it| Reverse(it.record.printed));
+        Ok(entries)
+    }
+
+    /// Matching entries, newest first.
+    ///
+    /// Only the day directories necessary to find `offset + limit` entries
+    /// are read, so paging through recent entries stays cheap.
+    pub fn entries(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Entry>> {
+        let mut entries = vec![];
+        let mut skipped = 0;
+
+        for (year, year_dir) in Self::numbered_subdirs(&self.dir)? {
+            for (month, month_dir) in Self::numbered_subdirs(&year_dir)? {
+                for (day, day_dir) in Self::numbered_subdirs(&month_dir)? {
+                    let Ok(date) = Date::new(year, month as i8, day as i8) else {
+                        continue;
+                    };
+                    if !filter.matches_day(date) {
+                        continue;
+                    }
+
+                    for entry in Self::entries_of_day(&day_dir)? {
+                        if !filter.matches(&entry.record) {
+                            continue;
+                        }
+                        if skipped < offset {
+                            skipped += 1;
+                            continue;
+                        }
+                        entries.push(entry);
+                        if entries.len() >= limit {
+                            return Ok(entries);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.image_path(id).is_ok_and(|it| it.is_file())
+    }
+
+    pub fn load_record(&self, id: &str) -> anyhow::Result<Record> {
+        let path = self.meta_path(id)?;
+        let bytes = fs::read(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to read archive metadata")?;
+        let record = serde_json::from_slice(&bytes)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to parse archive metadata")?;
+        Ok(record)
+    }
+
+    pub fn load_image(&self, id: &str) -> anyhow::Result<RgbaImage> {
+        let path = self.image_path(id)?;
+        let image = image::open(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to load archived image")?
+            .into_rgba8();
+        Ok(image)
+    }
+
+    /// The archived PNG file exactly as it was printed.
+    pub fn load_image_bytes(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        let path = self.image_path(id)?;
+        let bytes = fs::read(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to read archived image")?;
+        Ok(bytes)
+    }
+}
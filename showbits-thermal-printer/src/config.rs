@@ -0,0 +1,90 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use crate::{printer::PrinterConfig, printers::Printers};
+
+/// A set of named printers, usually loaded from a JSON file.
+///
+/// ```json
+/// {
+///   "default": "kitchen",
+///   "printers": {
+///     "kitchen": { "queue": "queue/kitchen", "device": "/dev/usb/lp0" },
+///     "office": { "queue": "queue/office", "device": "tcp://office-printer", "width": 576 }
+///   }
+/// }
+/// ```
+#[derive(Deserialize)]
+pub struct Config {
+    /// Where jobs go if the request doesn't name a printer. Either the name of
+    /// a printer or `pool`.
+    pub default: String,
+    pub printers: BTreeMap<String, PrinterSection>,
+}
+
+#[derive(Deserialize)]
+pub struct PrinterSection {
+    /// Path to the printer's queue directory.
+    pub queue: PathBuf,
+    #[serde(flatten)]
+    pub config: PrinterConfig,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to read config")?;
+        let config = serde_json::from_str::<Self>(&text)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to parse config")?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.printers.is_empty() {
+            bail!("no printers configured");
+        }
+
+        if self.default != Printers::POOL && !self.printers.contains_key(&self.default) {
+            bail!("default printer {:?} doesn't exist", self.default);
+        }
+
+        for (name, printer) in &self.printers {
+            if name == Printers::POOL {
+                bail!("printers must not be called {:?}", Printers::POOL);
+            }
+
+            // Names end up in URLs without being escaped.
+            let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if name.is_empty() || !name.chars().all(valid_char) {
+                bail!("printer name {name:?} may only contain letters, digits, - and _");
+            }
+
+            let width = printer.config.width;
+            if width == 0 || width % 8 != 0 {
+                bail!("width of printer {name:?} must be a positive multiple of 8");
+            }
+            let max_width = printer.config.raster.max_width();
+            if width > max_width {
+                bail!("width of printer {name:?} must be at most {max_width} in its raster mode");
+            }
+
+            let conversion = &printer.config.conversion;
+            if !(0.0..=1.0).contains(&conversion.threshold) {
+                bail!("threshold of printer {name:?} must be between 0 and 1");
+            }
+            if !(conversion.gamma.is_finite() && conversion.gamma > 0.0) {
+                bail!("gamma of printer {name:?} must be positive");
+            }
+        }
+
+        Ok(())
+    }
+}
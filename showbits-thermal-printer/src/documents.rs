@@ -1,17 +1,34 @@
+use rust_embed::RustEmbed;
 use showbits_typst::Typst;
 
+use crate::server::Server;
+
 pub mod banner;
 pub mod calendar;
+pub mod calibration;
 pub mod cells;
 pub mod chat;
 pub mod egg;
 pub mod image;
+pub mod plaintext;
 pub mod sunrise;
 pub mod text;
 pub mod tictactoe;
+pub mod typst;
 pub mod xkcd;
 pub mod catfishing;
 
-fn typst_with_lib() -> Typst {
-    Typst::new().with_file("/lib/main.typ", include_str!("documents/lib/main.typ"))
+/// Typst packages embedded into the binary.
+///
+/// Packages can be vendored into this directory using `meta/vendor-packages`.
+#[derive(RustEmbed)]
+#[folder = "packages"]
+struct Packages;
+
+fn typst_with_lib(server: &Server) -> Typst {
+    Typst::new()
+        .with_embedded_packages(Some(|path| Packages::get(path).map(|it| it.data)))
+        .with_package_dir(server.packages.clone())
+        .with_network(server.network)
+        .with_file("/lib/main.typ", include_str!("documents/lib/main.typ"))
 }
@@ -1,7 +1,7 @@
-use axum::{Form, extract::State};
+use axum::{Form, extract::State, response::Response};
 use serde::{Deserialize, Serialize};
 
-use crate::server::{Server, somehow};
+use crate::server::{Server, options::JobOptions, somehow};
 
 #[derive(Serialize)]
 struct Data {
@@ -15,15 +15,19 @@ pub struct FormData {
     pub feed: Option<bool>,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<()> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let data = Data {
         text: form.text,
         feed: form.feed.unwrap_or(true),
     };
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await
+    server.print_typst(options, "banner", &data, typst).await
 }
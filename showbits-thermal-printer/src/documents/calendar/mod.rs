@@ -1,8 +1,8 @@
-use axum::{Form, extract::State};
+use axum::{Form, extract::State, response::Response};
 use jiff::Zoned;
 use serde::{Deserialize, Serialize};
 
-use crate::server::{Server, somehow};
+use crate::server::{Server, options::JobOptions, somehow};
 
 #[derive(Serialize)]
 struct Data {
@@ -18,7 +18,11 @@ pub struct FormData {
     pub feed: Option<bool>,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<()> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let date = Zoned::now().date();
 
     let data = Data {
@@ -27,9 +31,9 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         feed: form.feed.unwrap_or(true),
     };
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await
+    server.print_typst(options, "calendar", &data, typst).await
 }
@@ -0,0 +1,68 @@
+#import "lib/main.typ" as lib;
+#show: it => lib.init(it)
+
+#let data = json("data.json")
+#let conversion = data.conversion
+
+#set par(spacing: 12pt)
+
+= Calibration
+
+Printer: #data.printer \
+Threshold: #conversion.threshold \
+Gamma: #conversion.gamma \
+Weighting: #conversion.weighting \
+Alpha: #conversion.alpha
+
+// Steps of gray, labeled with their brightness in percent. The step where black
+// turns into white shows the effective threshold.
+#let steps = range(0, 101, step: 5)
+#let ramp(color-at) = grid(
+  columns: (1fr,) * 7,
+  row-gutter: 2pt,
+  ..steps.map(p => box(
+    width: 100%,
+    height: 32pt,
+    fill: color-at(p),
+    stroke: 1pt + black,
+  )[
+    #set align(center + horizon)
+    #box(fill: white, inset: 1pt, text(size: 16pt)[#p])
+  ])
+)
+
+== Gray
+#ramp(p => luma(p * 1%))
+
+== Color
+#ramp(p => rgb(100%, p * 1%, p * 1%))
+#ramp(p => rgb(p * 1%, 100%, p * 1%))
+#ramp(p => rgb(p * 1%, p * 1%, 100%))
+
+== Alpha
+#ramp(p => black.transparentize(p * 1%))
+
+== Gradient
+#box(width: 100%, height: 32pt, fill: gradient.linear(black, white))
+
+== Lines
+#for thickness in (0.25pt, 0.5pt, 0.75pt, 1pt, 1.5pt, 2pt) [
+  #box(width: 25%)[#thickness]
+  #box(width: 75%, line(length: 100%, stroke: thickness))
+  \
+]
+#for angle in (5deg, 30deg, 60deg) [
+  #box(width: 25%)[#angle]
+  #box(height: 64pt * calc.sin(angle), line(length: 64pt, angle: angle, stroke: 1pt))
+  \
+]
+
+== Text
+#for size in (8pt, 10pt, 12pt, 16pt) [
+  #text(font: "Libertinus Serif", size: size)[#size Quick brown fox] \
+  #text(font: "DejaVu Sans Mono", size: size)[#size Quick brown fox] \
+]
+
+#if data.feed {
+  lib.feed
+}
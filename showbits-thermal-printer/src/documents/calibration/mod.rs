@@ -0,0 +1,42 @@
+use axum::{Form, extract::State, response::Response};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    printer::Conversion,
+    server::{Server, options::JobOptions, somehow},
+};
+
+#[derive(Serialize)]
+struct Data {
+    printer: String,
+    conversion: Conversion,
+    feed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    pub feed: Option<bool>,
+}
+
+/// Print gray ramps and thin lines and text, to find out which threshold and
+/// gamma work best for a printer. The printer's current settings are printed
+/// as well, so the results can be compared.
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
+    let data = Data {
+        printer: options.printer.name.clone(),
+        conversion: options.printer.conversion,
+        feed: form.feed.unwrap_or(true),
+    };
+
+    let typst = super::typst_with_lib(&server)
+        .with_json("/data.json", &data)
+        .with_main_file(include_str!("main.typ"));
+
+    server
+        .print_typst(options, "calibration", &data, typst)
+        .await
+}
@@ -1,11 +1,7 @@
-use axum::{
-    Form,
-    extract::State,
-    response::{IntoResponse, Response},
-};
+use axum::{Form, extract::State, response::Response};
 use serde::{Deserialize, Serialize};
 
-use crate::server::{Server, somehow};
+use crate::server::{Server, options::JobOptions, somehow};
 
 #[derive(Serialize, Deserialize)]
 struct ArticleInfo {
@@ -26,7 +22,11 @@ pub struct FormData {
     pub feed: bool,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<Response> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let client = reqwest::Client::builder()
         .user_agent(crate::USER_AGENT)
         .build()?;
@@ -46,10 +46,11 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         feed: form.feed,
     };
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await?;
-    Ok(().into_response())
+    server
+        .print_typst(options, "catfishing", &data, typst)
+        .await
 }
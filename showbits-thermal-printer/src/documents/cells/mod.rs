@@ -1,14 +1,11 @@
 use std::{collections::VecDeque, io::Cursor};
 
 use anyhow::Context;
-use axum::{Form, extract::State};
+use axum::{Form, extract::State, response::Response};
 use image::{ImageFormat, Rgba, RgbaImage, imageops};
 use serde::{Deserialize, Serialize};
 
-use crate::{
-    printer::Printer,
-    server::{Server, somehow},
-};
+use crate::server::{Server, options::JobOptions, somehow};
 
 const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
 const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
@@ -112,11 +109,15 @@ pub struct FormData {
     pub feed: Option<bool>,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<()> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let show_rule = form.show_rule.unwrap_or(true);
     let scale = form.scale.unwrap_or(4).clamp(1, 16);
     let rows = form.rows.unwrap_or(128 * 4 / scale).clamp(1, 1024 / scale);
-    let cols = Printer::WIDTH / scale;
+    let cols = options.printer.width / scale;
 
     let (rule, image) = match form.rule {
         Some(rule) => (rule, generate_image(rows, cols, rule)),
@@ -141,10 +142,10 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         .context("failed to encode image as png")
         .map_err(somehow::Error)?;
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_file("/image.png", bytes)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await
+    server.print_typst(options, "cells", &data, typst).await
 }
@@ -1,7 +1,7 @@
-use axum::{Form, extract::State};
+use axum::{Form, extract::State, response::Response};
 use serde::{Deserialize, Serialize};
 
-use crate::server::{Server, somehow};
+use crate::server::{Server, options::JobOptions, somehow};
 
 #[derive(Serialize)]
 struct Data {
@@ -21,7 +21,11 @@ pub struct FormData {
     pub feed: Option<bool>,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<()> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let seed = form.seed.unwrap_or_else(rand::random);
 
     let data = Data {
@@ -34,7 +38,7 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         feed: form.feed.unwrap_or(true),
     };
 
-    let mut typst = super::typst_with_lib()
+    let mut typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_main_file(include_str!("main.typ"));
 
@@ -54,5 +58,5 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         typst.add_file(format!("/eggs/bad/pattern_{i:02}.png"), *pattern);
     }
 
-    server.print_typst(typst).await
+    server.print_typst(options, "egg", &data, typst).await
 }
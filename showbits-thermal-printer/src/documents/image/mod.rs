@@ -16,7 +16,7 @@ use mark::dither::{AlgoFloydSteinberg, AlgoStucki, Algorithm, DiffEuclid, Palett
 use palette::LinSrgb;
 use serde::Serialize;
 
-use crate::server::{Server, somehow, statuscode::status_code};
+use crate::server::{Server, options::JobOptions, somehow, statuscode::status_code};
 
 pub fn dither(
     mut image: RgbaImage,
@@ -88,7 +88,11 @@ struct Data {
     feed: bool,
 }
 
-pub async fn post(server: State<Server>, mut multipart: Multipart) -> somehow::Result<Response> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    mut multipart: Multipart,
+) -> somehow::Result<Response> {
     let mut image = None;
     let mut algo = "stucki".to_string();
     let mut rotate = false;
@@ -136,7 +140,9 @@ pub async fn post(server: State<Server>, mut multipart: Multipart) -> somehow::R
     };
 
     // Export original image if requested
-    if let Some(dir) = &server.originals {
+    if let Some(dir) = &server.originals
+        && !options.preview
+    {
         fs::create_dir_all(dir)?;
         let path = dir.join(Timestamp::now().as_millisecond().to_string());
         fs::write(path, &image)?;
@@ -160,7 +166,7 @@ pub async fn post(server: State<Server>, mut multipart: Multipart) -> somehow::R
     }
 
     // Dither image
-    let max_width = Some(384);
+    let max_width = Some(options.printer.width);
     let max_height = Some(1024);
     let image = dither(image, max_width, max_height, bright, &algo).map_err(somehow::Error)?;
 
@@ -171,13 +177,12 @@ pub async fn post(server: State<Server>, mut multipart: Multipart) -> somehow::R
         .context("failed to encode image as png")
         .map_err(somehow::Error)?;
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_file("/image.png", bytes)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await?;
-    Ok(().into_response())
+    server.print_typst(options, "image", &data, typst).await
 }
 
 #[derive(Serialize)]
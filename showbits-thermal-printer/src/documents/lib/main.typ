@@ -1,4 +1,4 @@
-#let width = 384pt
+#let width = int(sys.inputs.at("width", default: "384")) * 1pt
 
 #let init(it) = {
   set page(
@@ -0,0 +1,30 @@
+#import "lib/main.typ" as lib;
+#show: it => lib.init(it)
+
+#let data = json("data.json")
+
+// Imitate the printer's built-in 12x24 dot font, which wraps lines at the edge
+// of the paper instead of between words.
+#set text(size: 24pt)
+#set text(stroke: 0.5pt) if data.bold
+#set par(leading: 6pt, spacing: 6pt)
+#show regex("."): it => it + sym.zws
+
+#let sx = if data.double_width { 2 } else { 1 }
+#let sy = if data.double_height { 2 } else { 1 }
+#let alignment = (left: left, center: center, right: right).at(data.align)
+
+#scale(
+  x: sx * 100%,
+  y: sy * 100%,
+  origin: top + left,
+  reflow: true,
+  block(width: 100% / sx, {
+    set align(alignment)
+    if data.underline { underline(data.text) } else { data.text }
+  }),
+)
+
+#if data.feed {
+  lib.feed
+}
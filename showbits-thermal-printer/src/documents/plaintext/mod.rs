@@ -0,0 +1,72 @@
+use axum::{Form, extract::State, response::Response};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    printer::{Align, Text},
+    server::{Server, options::JobOptions, somehow},
+};
+
+#[derive(Serialize)]
+struct Data {
+    text: String,
+    bold: bool,
+    underline: bool,
+    double_width: bool,
+    double_height: bool,
+    align: Align,
+    feed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    pub text: String,
+    pub bold: Option<bool>,
+    pub underline: Option<bool>,
+    pub double_width: Option<bool>,
+    pub double_height: Option<bool>,
+    pub align: Option<Align>,
+    /// Replacement for characters the printer's built-in font doesn't have.
+    pub fallback: Option<char>,
+    pub feed: Option<bool>,
+    /// Print using the printer's built-in font instead of as an image.
+    pub native: Option<bool>,
+}
+
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
+    let text = Text {
+        text: form.text,
+        bold: form.bold.unwrap_or(false),
+        underline: form.underline.unwrap_or(false),
+        double_width: form.double_width.unwrap_or(false),
+        double_height: form.double_height.unwrap_or(false),
+        align: form.align.unwrap_or_default(),
+        fallback: form.fallback.unwrap_or('?'),
+        feed: form.feed.unwrap_or(true),
+    };
+
+    let data = Data {
+        text: text.text.clone(),
+        bold: text.bold,
+        underline: text.underline,
+        double_width: text.double_width,
+        double_height: text.double_height,
+        align: text.align,
+        feed: text.feed,
+    };
+
+    let typst = super::typst_with_lib(&server)
+        .with_json("/data.json", &data)
+        .with_main_file(include_str!("main.typ"));
+
+    if form.native.unwrap_or(true) {
+        server
+            .print_text(options, "plaintext", &data, text, typst)
+            .await
+    } else {
+        server.print_typst(options, "plaintext", &data, typst).await
+    }
+}
@@ -1,10 +1,10 @@
 use anyhow::anyhow;
-use axum::{Form, extract::State};
+use axum::{Form, extract::State, response::Response};
 use jiff::{Timestamp, ToSpan, Zoned, civil, tz::TimeZone};
 use serde::{Deserialize, Serialize};
 use sunrise::{Coordinates, SolarDay, SolarEvent};
 
-use crate::server::{Server, somehow};
+use crate::server::{Server, options::JobOptions, somehow};
 
 #[derive(Serialize)]
 struct Data {
@@ -23,7 +23,11 @@ pub struct FormData {
     pub feed: Option<bool>,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<()> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let now = Zoned::now();
     let now_date_utc = now.with_time_zone(TimeZone::UTC).date();
     let year = form.year.unwrap_or(now_date_utc.year());
@@ -69,9 +73,9 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         feed: form.feed.unwrap_or(true),
     };
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await
+    server.print_typst(options, "sunrise", &data, typst).await
 }
@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Multipart, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::server::{Server, options::JobOptions, somehow, statuscode::status_code};
+
+/// Print an arbitrary typst document.
+///
+/// The `main` field contains the main file, the optional `data` field is made
+/// available as `data.json`, and every `file` field is made available under
+/// its file name. The library is available at `lib/main.typ` like in all other
+/// documents.
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    mut multipart: Multipart,
+) -> somehow::Result<Response> {
+    let mut typst = super::typst_with_lib(&server);
+    let mut has_main = false;
+    let mut data = serde_json::Value::Null;
+
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("main") => {
+                typst.add_main_file(Vec::from(field.bytes().await?));
+                has_main = true;
+            }
+            Some("data") => {
+                let bytes = field.bytes().await?;
+                // Recorded with the job, but only if it is valid JSON.
+                data = serde_json::from_slice(&bytes).unwrap_or_default();
+                typst.add_file("/data.json", Vec::from(bytes));
+            }
+            Some("file") => {
+                let Some(name) = field.file_name() else {
+                    return Ok(status_code(StatusCode::UNPROCESSABLE_ENTITY));
+                };
+                let path = format!("/{}", name.trim_start_matches('/'));
+                typst.add_file(path, Vec::from(field.bytes().await?));
+            }
+            _ => {}
+        }
+    }
+
+    if !has_main {
+        return Ok(status_code(StatusCode::UNPROCESSABLE_ENTITY));
+    }
+
+    // Unlike the other documents, errors in the source are the client's fault.
+    match server.print_typst(options, "typst", &data, typst).await {
+        Err(err) if err.0.is::<showbits_typst::Error>() => {
+            let mut response = err.into_response();
+            *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+            Ok(response)
+        }
+        result => result,
+    }
+}
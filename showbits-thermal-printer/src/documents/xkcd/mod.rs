@@ -1,15 +1,11 @@
 use std::io::Cursor;
 
 use anyhow::Context;
-use axum::{
-    Form,
-    extract::State,
-    response::{IntoResponse, Response},
-};
+use axum::{Form, extract::State, response::Response};
 use image::{ImageFormat, imageops};
 use serde::{Deserialize, Serialize};
 
-use crate::server::{Server, somehow};
+use crate::server::{Server, options::JobOptions, somehow};
 
 #[derive(Deserialize)]
 struct ComicInfo {
@@ -37,7 +33,11 @@ pub struct FormData {
     pub feed: Option<bool>,
 }
 
-pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow::Result<Response> {
+pub async fn post(
+    server: State<Server>,
+    options: JobOptions,
+    Form(form): Form<FormData>,
+) -> somehow::Result<Response> {
     let client = reqwest::Client::builder()
         .user_agent(crate::USER_AGENT)
         .build()?;
@@ -65,7 +65,7 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
     }
 
     if data.dither {
-        let max_width = Some(384);
+        let max_width = Some(options.printer.width);
         let max_height = Some(1024);
         image = super::image::dither(
             image,
@@ -83,11 +83,10 @@ pub async fn post(server: State<Server>, Form(form): Form<FormData>) -> somehow:
         .context("failed to encode image as png")
         .map_err(somehow::Error)?;
 
-    let typst = super::typst_with_lib()
+    let typst = super::typst_with_lib(&server)
         .with_json("/data.json", &data)
         .with_file("/image.png", bytes)
         .with_main_file(include_str!("main.typ"));
 
-    server.print_typst(typst).await?;
-    Ok(().into_response())
+    server.print_typst(options, "xkcd", &data, typst).await
 }
@@ -1,46 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc as std_mpsc,
+    thread,
+};
+
+use anyhow::anyhow;
+use image::RgbaImage;
 use showbits_typst::Typst;
 use tokio::sync::{mpsc, oneshot};
 
-use crate::persistent_printer::PersistentPrinter;
+use crate::{
+    jobs::{JobId, JobState, Jobs},
+    persistent_printer::{PersistentPrinter, PrintOutcome},
+    printer::Text,
+    render_pool::RenderPool,
+};
 
-#[expect(clippy::large_enum_variant)]
 pub enum Command {
     Backlog,
-    Typst(Typst, oneshot::Sender<anyhow::Result<()>>),
+    Pause,
+    Resume,
+    Typst(JobId, Typst, oneshot::Sender<anyhow::Result<()>>),
+    /// An image that has already been rendered, e.g. when reprinting.
+    Image(JobId, RgbaImage, oneshot::Sender<anyhow::Result<()>>),
+    /// Text to be printed natively, and the same text as a typst document in
+    /// case that isn't possible.
+    Text(JobId, Text, Typst, oneshot::Sender<anyhow::Result<()>>),
+}
+
+/// What a job needs to be printed.
+enum Output {
+    Image(anyhow::Result<RgbaImage>),
+    /// The text and its typst document rendered as an image, which is used if
+    /// the text can't be printed natively and for the archive.
+    Text(Text, anyhow::Result<RgbaImage>),
+}
+
+/// A job that is ready to be printed.
+struct Rendered {
+    id: JobId,
+    output: Output,
+    tx: oneshot::Sender<anyhow::Result<()>>,
+}
+
+enum Event {
+    Command(Box<Command>),
+    /// A job has been rendered. Jobs are numbered in the order they were
+    /// received in.
+    Rendered(u64, Rendered),
+    /// No more commands will arrive.
+    Closed,
 }
 
+/// Renders and prints the jobs for a single printer.
+///
+/// Documents are rendered by the render pool, possibly several at once and in
+/// any order. The drawer's own thread does nothing but talk to the printer, and
+/// it prints jobs in the order they were submitted in.
 pub struct Drawer {
-    rx: mpsc::Receiver<Command>,
+    events: std_mpsc::Receiver<Event>,
+    events_tx: std_mpsc::Sender<Event>,
+    /// Returns permits to the thread receiving commands once they have been
+    /// handled.
+    permits: std_mpsc::SyncSender<()>,
+    pool: RenderPool,
     printer: PersistentPrinter,
+    jobs: Jobs,
+
+    /// Number of the next job to be received.
+    next_received: u64,
+    /// Number of the next job to be printed.
+    next_printed: u64,
+    /// Rendered jobs waiting for earlier jobs to finish rendering.
+    rendered: BTreeMap<u64, Rendered>,
 }
 
 impl Drawer {
-    pub fn new(rx: mpsc::Receiver<Command>, printer: PersistentPrinter) -> Self {
-        Self { rx, printer }
+    /// How many commands may be rendering or waiting to be printed at once.
+    ///
+    /// Once this many are in progress, no more commands are received, so that
+    /// the senders have to wait instead of piling up jobs in memory.
+    const MAX_PENDING: usize = 8;
+
+    pub fn new(
+        mut rx: mpsc::Receiver<Command>,
+        pool: RenderPool,
+        printer: PersistentPrinter,
+        jobs: Jobs,
+    ) -> Self {
+        let (events_tx, events) = std_mpsc::channel();
+
+        let (permits, permits_rx) = std_mpsc::sync_channel(Self::MAX_PENDING);
+        for _ in 0..Self::MAX_PENDING {
+            let _ = permits.try_send(());
+        }
+
+        // Commands and rendered jobs arrive on the same channel so the drawer
+        // can wait for both at once. Each command needs a permit first.
+        let tx = events_tx.clone();
+        thread::spawn(move || {
+            while permits_rx.recv().is_ok() {
+                let Some(command) = rx.blocking_recv() else {
+                    let _ = tx.send(Event::Closed);
+                    return;
+                };
+                if tx.send(Event::Command(Box::new(command))).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            events,
+            events_tx,
+            permits,
+            pool,
+            printer,
+            jobs,
+            next_received: 0,
+            next_printed: 0,
+            rendered: BTreeMap::new(),
+        }
     }
 
     pub fn run(&mut self) -> anyhow::Result<()> {
-        while let Some(command) = self.rx.blocking_recv() {
-            self.run_cmd(command)?;
+        let mut closed = false;
+        while let Ok(event) = self.events.recv() {
+            match event {
+                Event::Command(command) => self.run_cmd(*command),
+                Event::Rendered(number, rendered) => {
+                    self.rendered.insert(number, rendered);
+                }
+                Event::Closed => closed = true,
+            }
+            self.print_rendered();
+
+            // Jobs that were received before the channel closed are still
+            // rendered and printed.
+            if closed && self.next_printed == self.next_received {
+                break;
+            }
         }
         Ok(())
     }
 
-    fn run_cmd(&mut self, command: Command) -> anyhow::Result<()> {
+    fn run_cmd(&mut self, command: Command) {
         match command {
             Command::Backlog => {
-                self.printer.print_backlog()?;
+                self.printer.print_backlog();
+                self.release_permit();
+            }
+            Command::Pause => {
+                self.printer.set_paused(true);
+                self.release_permit();
             }
-            Command::Typst(typst, tx) => {
-                let _ = tx.send(self.run_cmd_typst(typst));
+            Command::Resume => {
+                self.printer.set_paused(false);
+                self.printer.print_backlog();
+                self.release_permit();
+            }
+            Command::Typst(id, typst, tx) => {
+                let jobs = self.jobs.clone();
+                self.render(id, tx, move || {
+                    Output::Image(Self::render_typst(&jobs, id, &typst))
+                });
+            }
+            Command::Image(id, image, tx) => {
+                // Even though there is nothing to render, the image must wait
+                // for earlier jobs.
+                let number = self.next_number();
+                let output = Output::Image(Ok(image));
+                self.rendered.insert(number, Rendered { id, output, tx });
+            }
+            Command::Text(id, text, typst, tx) => {
+                let jobs = self.jobs.clone();
+                self.render(id, tx, move || {
+                    Output::Text(text, Self::render_typst(&jobs, id, &typst))
+                });
             }
         }
-        Ok(())
     }
 
-    fn run_cmd_typst(&mut self, typst: Typst) -> anyhow::Result<()> {
-        let image = typst.render()?;
-        self.printer.print_image(&image)?;
+    /// Allow receiving another command after one has been handled.
+    fn release_permit(&self) {
+        let _ = self.permits.try_send(());
+    }
+
+    fn next_number(&mut self) -> u64 {
+        let number = self.next_received;
+        self.next_received += 1;
+        number
+    }
+
+    /// Render a job in the render pool.
+    fn render(
+        &mut self,
+        id: JobId,
+        tx: oneshot::Sender<anyhow::Result<()>>,
+        render: impl FnOnce() -> Output + Send + 'static,
+    ) {
+        let number = self.next_number();
+        let events_tx = self.events_tx.clone();
+        self.pool.spawn(move || {
+            let output = render();
+            let _ = events_tx.send(Event::Rendered(number, Rendered { id, output, tx }));
+        });
+    }
+
+    fn render_typst(jobs: &Jobs, id: JobId, typst: &Typst) -> anyhow::Result<RgbaImage> {
+        // Later jobs can only be printed once this one has been rendered, so
+        // rendering must always produce a result.
+        let rendered = panic::catch_unwind(AssertUnwindSafe(|| typst.render()))
+            .map_err(|_| anyhow!("rendering panicked"))??;
+        jobs.set_warnings(id, rendered.warnings);
+        Ok(rendered.image)
+    }
+
+    /// Print all rendered jobs whose predecessors have been printed.
+    fn print_rendered(&mut self) {
+        let printed = self.next_printed;
+        while let Some(rendered) = self.rendered.remove(&self.next_printed) {
+            self.next_printed += 1;
+            self.print(rendered);
+        }
+
+        // Evicting after every render would throw away results that the next
+        // job of the batch could have reused.
+        if self.next_printed != printed && self.next_printed == self.next_received {
+            Typst::evict_caches();
+        }
+    }
+
+    fn print(&mut self, rendered: Rendered) {
+        let Rendered { id, output, tx } = rendered;
+        let result = match output {
+            Output::Image(image) => image.and_then(|it| self.print_image(id, &it)),
+            Output::Text(text, image) => self.print_text(id, &text, image),
+        };
+        if let Err(err) = &result {
+            let error = format!("{err:#}");
+            self.jobs.set_state(id, JobState::Failed { error });
+        }
+        let _ = tx.send(result);
+        self.release_permit();
+    }
+
+    fn print_image(&mut self, id: JobId, image: &RgbaImage) -> anyhow::Result<()> {
+        self.jobs.set_image(id, image.clone());
+        self.jobs.set_state(id, JobState::Printing);
+        let state = match self.printer.print_image(id, image)? {
+            PrintOutcome::Printed => JobState::Printed,
+            PrintOutcome::Queued => JobState::QueuedOffline,
+            PrintOutcome::Paused => JobState::QueuedPaused,
+        };
+        self.jobs.set_state(id, state);
+
         Ok(())
     }
+
+    fn print_text(
+        &mut self,
+        id: JobId,
+        text: &Text,
+        image: anyhow::Result<RgbaImage>,
+    ) -> anyhow::Result<()> {
+        self.jobs.set_state(id, JobState::Printing);
+        match self.printer.print_text(id, text) {
+            Ok(true) => {
+                self.jobs.set_state(id, JobState::Printed);
+                self.archive_text(id, &image);
+                return Ok(());
+            }
+            Ok(false) => {}
+            Err(err) => println!("Failed to print text, printing it as an image instead: {err:#}"),
+        }
+
+        // Unlike text, images can be queued if the printer is unavailable.
+        self.print_image(id, &image?)
+    }
+
+    /// The archive only contains images, so natively printed text is archived
+    /// the way it would have looked if it had been printed as an image.
+    fn archive_text(&self, id: JobId, image: &anyhow::Result<RgbaImage>) {
+        if !self.printer.is_archiving() {
+            return;
+        }
+        match image {
+            Ok(image) => self.printer.archive_job(id, image),
+            Err(err) => println!("Failed to render text for the archive: {err}"),
+        }
+    }
 }
@@ -0,0 +1,12 @@
+use anyhow::bail;
+
+/// Check the id of a queue or archive entry before using it in a path.
+///
+/// Ids may come from untrusted sources, so they must not be able to reference
+/// files outside the directory containing the entries.
+pub fn check(id: &str) -> anyhow::Result<()> {
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+        bail!("invalid entry id {id:?}");
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind};
+
+use crate::{
+    drawer::Command,
+    printers::{PrinterHandle, Printers},
+};
+
+/// Drains a printer's backlog as soon as its device file appears, e.g. when a
+/// USB printer is plugged in or switched on.
+///
+/// While the printer is disconnected, its device file and sometimes even the
+/// file's directory don't exist. The closest existing ancestor directory is
+/// watched instead, and the watches move along as directories come and go.
+pub struct Hotplug {
+    printers: Vec<(PathBuf, PrinterHandle)>,
+    watcher: RecommendedWatcher,
+    rx: mpsc::Receiver<notify::Result<Event>>,
+    watched: HashSet<PathBuf>,
+}
+
+impl Hotplug {
+    /// Give udev a moment to finish setting up new device files, for example
+    /// their permissions, before trying to print.
+    const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+    /// Start watching the device files of all printers that have one.
+    pub fn spawn(printers: &Printers) -> anyhow::Result<()> {
+        let printers = printers
+            .iter()
+            .filter_map(|printer| {
+                let path = printer.device.as_ref()?.watch_path()?;
+                Some((path.to_path_buf(), printer.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        if printers.is_empty() {
+            return Ok(());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx).context("Failed to create file watcher")?;
+        let mut hotplug = Self {
+            printers,
+            watcher,
+            rx,
+            watched: HashSet::new(),
+        };
+        hotplug.update_watches();
+
+        thread::Builder::new()
+            .name("hotplug".to_string())
+            .spawn(move || hotplug.run())?;
+
+        Ok(())
+    }
+
+    fn closest_existing_dir(path: &Path) -> Option<&Path> {
+        path.ancestors().skip(1).find(|it| it.is_dir())
+    }
+
+    fn update_watches(&mut self) {
+        // Watches of removed directories have been dropped automatically.
+        self.watched.retain(|it| it.is_dir());
+
+        let wanted = self
+            .printers
+            .iter()
+            .filter_map(|(path, _)| Self::closest_existing_dir(path))
+            .map(|it| it.to_path_buf())
+            .collect::<HashSet<_>>();
+
+        for dir in self.watched.difference(&wanted) {
+            let _ = self.watcher.unwatch(dir);
+        }
+
+        for dir in wanted.difference(&self.watched) {
+            if let Err(err) = self.watcher.watch(dir, RecursiveMode::NonRecursive) {
+                println!("Failed to watch {}: {err}", dir.display());
+            }
+        }
+
+        self.watched = wanted;
+    }
+
+    /// Whether the event may mean that a file has appeared or become usable.
+    ///
+    /// Writes to a device file also cause events, so reacting to every event
+    /// would make printing trigger itself.
+    fn is_appearance(event: &Event) -> bool {
+        matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_) | ModifyKind::Metadata(_))
+        )
+    }
+
+    /// The paths of files that may have appeared.
+    fn appeared_paths(event: notify::Result<Event>) -> Vec<PathBuf> {
+        match event {
+            Ok(event) if Self::is_appearance(&event) => event.paths,
+            Ok(_) => vec![],
+            Err(err) => {
+                println!("Failed to watch printer devices: {err}");
+                vec![]
+            }
+        }
+    }
+
+    fn run(mut self) {
+        while let Ok(event) = self.rx.recv() {
+            let mut paths = Self::appeared_paths(event);
+
+            // Removing a watched directory also removes its watch, so the
+            // watches must be updated after every event, not just appearances.
+            self.update_watches();
+            if paths.is_empty() {
+                continue;
+            }
+            thread::sleep(Self::SETTLE_DELAY);
+            paths.extend(self.rx.try_iter().flat_map(Self::appeared_paths));
+
+            // A new directory may already contain the device file by the time
+            // it is being watched, so its appearance counts as well.
+            self.update_watches();
+            for (device, printer) in &self.printers {
+                let affected = paths.iter().any(|it| device.starts_with(it));
+                if affected && device.exists() {
+                    println!(
+                        "Printer {:?} appeared at {}",
+                        printer.name,
+                        device.display()
+                    );
+                    let _ = printer.tx.blocking_send(Command::Backlog);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use image::RgbaImage;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use showbits_typst::Diagnostic;
+
+use crate::printer::Cut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct JobId(pub u64);
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum JobState {
+    Rendering,
+    Printing,
+    QueuedOffline,
+    QueuedPaused,
+    Printed,
+    Failed { error: String },
+}
+
+/// A failed print attempt after part of the job's image had already been
+/// printed. The next attempt only prints the remaining rows.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialPrint {
+    pub time: Timestamp,
+    pub printed_rows: u32,
+    pub total_rows: u32,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: JobId,
+    pub document: String,
+    /// Name of the printer the job was sent to.
+    pub printer: String,
+    /// The data the document was rendered with.
+    pub params: serde_json::Value,
+    pub submitter: Option<String>,
+    /// Overrides how the printer cuts the paper after this job.
+    pub cut: Option<Cut>,
+    pub submitted: Timestamp,
+    pub updated: Timestamp,
+    #[serde(flatten)]
+    pub state: JobState,
+    /// Warnings emitted while compiling the document.
+    pub warnings: Vec<Diagnostic>,
+    pub partial_prints: Vec<PartialPrint>,
+}
+
+struct Inner {
+    next_id: u64,
+    jobs: BTreeMap<JobId, Job>,
+    /// The final bitmaps of the most recent jobs, exactly as they were handed
+    /// to the printer.
+    images: BTreeMap<JobId, Arc<RgbaImage>>,
+}
+
+/// Keeps track of the state of recently submitted jobs.
+///
+/// Jobs are only held in memory, so the history is lost on restart.
+#[derive(Clone)]
+pub struct Jobs(Arc<Mutex<Inner>>);
+
+impl Jobs {
+    /// How many jobs to remember before forgetting the oldest ones.
+    const MAX_JOBS: usize = 1000;
+
+    /// How many bitmaps to remember. They are much larger than the jobs
+    /// themselves, so only the most recent few are kept.
+    const MAX_IMAGES: usize = 20;
+
+    pub fn new() -> Self {
+        // Starting at the current time in milliseconds means ids stay unique
+        // across restarts, as long as we don't print more than one job per
+        // millisecond on average.
+        let next_id = Timestamp::now().as_millisecond().max(0) as u64;
+
+        Self(Arc::new(Mutex::new(Inner {
+            next_id,
+            jobs: BTreeMap::new(),
+            images: BTreeMap::new(),
+        })))
+    }
+
+    pub fn submit(
+        &self,
+        document: impl ToString,
+        printer: impl ToString,
+        params: serde_json::Value,
+        submitter: Option<String>,
+        cut: Option<Cut>,
+    ) -> JobId {
+        let mut inner = self.0.lock().unwrap();
+
+        let id = JobId(inner.next_id);
+        inner.next_id += 1;
+
+        let now = Timestamp::now();
+        let job = Job {
+            id,
+            document: document.to_string(),
+            printer: printer.to_string(),
+            params,
+            submitter,
+            cut,
+            submitted: now,
+            updated: now,
+            state: JobState::Rendering,
+            warnings: vec![],
+            partial_prints: vec![],
+        };
+        inner.jobs.insert(id, job);
+
+        while inner.jobs.len() > Self::MAX_JOBS {
+            inner.jobs.pop_first();
+        }
+
+        id
+    }
+
+    pub fn set_state(&self, id: JobId, state: JobState) {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.updated = Timestamp::now();
+            job.state = state;
+        }
+    }
+
+    pub fn set_warnings(&self, id: JobId, warnings: Vec<Diagnostic>) {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.warnings = warnings;
+        }
+    }
+
+    pub fn add_partial_print(&self, id: JobId, partial_print: PartialPrint) {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.updated = Timestamp::now();
+            job.partial_prints.push(partial_print);
+        }
+    }
+
+    pub fn set_image(&self, id: JobId, image: RgbaImage) {
+        let mut inner = self.0.lock().unwrap();
+        inner.images.insert(id, Arc::new(image));
+        while inner.images.len() > Self::MAX_IMAGES {
+            inner.images.pop_first();
+        }
+    }
+
+    pub fn image(&self, id: JobId) -> Option<Arc<RgbaImage>> {
+        self.0.lock().unwrap().images.get(&id).cloned()
+    }
+
+    /// The most recent job that still has its bitmap.
+    pub fn last_image(&self) -> Option<(JobId, Arc<RgbaImage>)> {
+        let inner = self.0.lock().unwrap();
+        let (id, image) = inner.images.last_key_value()?;
+        Some((*id, image.clone()))
+    }
+
+    pub fn get(&self, id: JobId) -> Option<Job> {
+        self.0.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    /// All remembered jobs, newest first.
+    pub fn list(&self) -> Vec<Job> {
+        self.0
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .rev()
+            .cloned()
+            .collect()
+    }
+}
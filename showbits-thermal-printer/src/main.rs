@@ -1,64 +1,277 @@
+mod archive;
 mod color;
+mod config;
 mod documents;
 mod drawer;
+mod entry_id;
+mod hotplug;
+mod jobs;
 mod persistent_printer;
 mod printer;
+mod printers;
+mod queue;
+mod render_pool;
 mod server;
 
-use std::{path::PathBuf, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf, thread, time::Duration};
 
+use anyhow::Context;
 use clap::Parser;
 use drawer::Command;
 use tokio::{runtime::Runtime, sync::mpsc};
 
-use self::{drawer::Drawer, persistent_printer::PersistentPrinter};
+use self::{
+    archive::Archive,
+    config::{Config, PrinterSection},
+    drawer::Drawer,
+    hotplug::Hotplug,
+    jobs::Jobs,
+    persistent_printer::PersistentPrinter,
+    printer::{Conversion, Cut, Device, Printer, PrinterConfig, RasterMode, Weighting},
+    printers::{PrinterHandle, Printers},
+    queue::Queue,
+    render_pool::RenderPool,
+    server::Server,
+};
 
 const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 #[derive(Parser)]
 struct Args {
     /// Path to the queue directory.
-    queue: PathBuf,
+    #[arg(required_unless_present = "config")]
+    queue: Option<PathBuf>,
+
+    /// Load a set of named printers from a JSON file.
+    ///
+    /// Each printer has its own queue directory and the same settings as the
+    /// printer options below, which are ignored in that case. Requests can
+    /// choose a printer via the `printer` query parameter or header.
+    #[arg(long, short, conflicts_with = "queue")]
+    config: Option<PathBuf>,
 
     /// Address the web server will listen at.
     #[arg(long, short, default_value = "localhost:8080")]
     address: String,
 
-    /// Path to the printer's USB device file.
+    /// The printer to print to.
     ///
-    /// Usually, this is located at `/dev/usb/lp0` or a similar location.
+    /// Either a USB device file like `file:///dev/usb/lp0` (or just
+    /// `/dev/usb/lp0`), a network printer like `tcp://host:9100`, or a serial
+    /// printer like `serial:///dev/ttyUSB0?baud=19200`.
     #[arg(long, short)]
-    printer: Option<PathBuf>,
+    printer: Option<Device>,
+
+    /// Command set used to print images.
+    ///
+    /// Use `graphics` for modern printers and `esc-star` for very old printers
+    /// that don't understand the default.
+    #[arg(long, value_enum, default_value_t)]
+    raster: RasterMode,
+
+    /// Brightness between 0 and 1 below which pixels are printed black.
+    #[arg(long, default_value_t = Conversion::default().threshold)]
+    threshold: f64,
+
+    /// Gamma applied to pixels before comparing them to the threshold.
+    ///
+    /// Values above 1 make anti-aliased lines and text thicker, values below 1
+    /// make them thinner.
+    #[arg(long, default_value_t = Conversion::default().gamma)]
+    gamma: f64,
+
+    /// How the color channels of a pixel are combined into its brightness.
+    #[arg(long, value_enum, default_value_t)]
+    weighting: Weighting,
+
+    /// Composite transparent pixels onto white instead of ignoring alpha.
+    #[arg(long)]
+    alpha: bool,
+
+    /// How to cut the paper after each job, if the printer has an auto-cutter.
+    #[arg(long, value_enum, default_value_t)]
+    cut: Cut,
+
+    /// Additional distance in dots to feed the paper before cutting.
+    ///
+    /// The printer already feeds the paper up to the cutter by itself.
+    #[arg(long, default_value_t = 0)]
+    cut_feed: u8,
+
+    /// Only cut after the last job in a burst of jobs instead of after every
+    /// job.
+    #[arg(long)]
+    cut_batch: bool,
+
+    /// Width of the printable area in pixels.
+    ///
+    /// Must be a multiple of 8. Usually 384 for 58 mm printers and 576 for
+    /// 80 mm printers.
+    #[arg(long, default_value_t = Printer::DEFAULT_WIDTH)]
+    width: u32,
 
     /// Export an image of whatever is printed here.
     #[arg(long, short)]
     export: Option<PathBuf>,
 
+    /// Keep every printed image along with information about its job in this
+    /// directory, organized by date.
+    #[arg(long)]
+    archive: Option<PathBuf>,
+
+    /// How often printing a queued image may fail before it is moved into the
+    /// dead letter directory inside the queue directory.
+    ///
+    /// Only failures caused by the image itself count, for example if it can't
+    /// be loaded. Problems with the printer just pause the queue.
+    #[arg(long, default_value_t = 5)]
+    max_attempts: u32,
+
+    /// How often to retry printing queued images and to check the printers'
+    /// status, in seconds.
+    ///
+    /// Printers connected via a device file are additionally checked as soon
+    /// as the file appears, e.g. when the printer is plugged in.
+    #[arg(long, default_value_t = 1)]
+    poll_interval: u64,
+
+    /// Number of documents rendered at the same time, shared by all printers.
+    ///
+    /// Defaults to the number of available CPU cores.
+    #[arg(long)]
+    render_threads: Option<usize>,
+
     /// Export the original images printed by the image document, before
     /// dithering or other manipulation.
     #[arg(long, short)]
     originals: Option<PathBuf>,
+
+    /// Directory containing typst packages.
+    ///
+    /// Packages are located at `{namespace}/{name}/{version}` inside the
+    /// directory, for example `preview/cetz/0.3.2`.
+    #[arg(long)]
+    packages: Option<PathBuf>,
+
+    /// Allow downloading typst packages that are neither embedded in the binary
+    /// nor available in the package directory.
+    #[arg(long)]
+    network: bool,
+}
+
+impl Args {
+    /// The single printer configured via the command line.
+    fn config(&self, queue: PathBuf) -> Config {
+        let name = "default".to_string();
+        let section = PrinterSection {
+            queue,
+            config: PrinterConfig {
+                device: self.printer.clone(),
+                width: self.width,
+                export: self.export.clone(),
+                raster: self.raster,
+                conversion: Conversion {
+                    threshold: self.threshold,
+                    gamma: self.gamma,
+                    weighting: self.weighting,
+                    alpha: self.alpha,
+                },
+                cut: self.cut,
+                cut_feed: self.cut_feed,
+                cut_batch: self.cut_batch,
+            },
+        };
+        Config {
+            default: name.clone(),
+            printers: BTreeMap::from([(name, section)]),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    let (tx, rx) = mpsc::channel(3);
+    let config = match (&args.config, &args.queue) {
+        (Some(path), _) => Config::load(path)?,
+        (None, Some(queue)) => args.config(queue.clone()),
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    config.validate()?;
+
+    let jobs = Jobs::new();
+    let archive = args.archive.map(Archive::new);
+
+    let render_threads = args
+        .render_threads
+        .or_else(|| thread::available_parallelism().ok().map(|it| it.get()))
+        .unwrap_or(1);
+    let pool = RenderPool::new(render_threads)?;
+
+    // Each printer gets its own drawer thread so that a slow or offline
+    // printer doesn't hold up the others. Whichever thread stops first stops
+    // the entire program.
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    let mut printers = vec![];
+    for (name, section) in config.printers {
+        let (tx, rx) = mpsc::channel(3);
+
+        let handle =
+            PrinterHandle::new(name.clone(), tx, Queue::new(section.queue), &section.config);
+        printers.push(handle.clone());
+
+        let jobs = jobs.clone();
+        let archive = archive.clone();
+        let pool = pool.clone();
+        let max_attempts = args.max_attempts;
+        let done_tx = done_tx.clone();
+        thread::Builder::new()
+            .name(format!("printer {name}"))
+            .spawn(move || {
+                // The printer is not Send, so it must be created in here.
+                let printer = PersistentPrinter::new(
+                    section.config,
+                    &handle,
+                    jobs.clone(),
+                    max_attempts,
+                    archive,
+                );
+                let result = Drawer::new(rx, pool, printer, jobs)
+                    .run()
+                    .with_context(|| format!("Printer {name:?} stopped"));
+                let _ = done_tx.send(result);
+            })?;
+    }
+    drop(done_tx);
+
+    let printers = Printers::new(printers, config.default);
+
+    let server = Server {
+        printers: printers.clone(),
+        jobs,
+        archive,
+        originals: args.originals,
+        packages: args.packages,
+        network: args.network,
+    };
 
-    let printer = PersistentPrinter::new(args.printer, args.export, args.queue);
-    let mut drawer = Drawer::new(rx, printer);
+    if let Err(err) = Hotplug::spawn(&printers) {
+        println!("{err:#}, falling back to polling only");
+    }
 
+    let poll_interval = Duration::from_secs(args.poll_interval.max(1));
     let runtime = Runtime::new()?;
-    runtime.spawn(server::run(tx.clone(), args.address, args.originals));
+    runtime.spawn(server::run(server, args.address));
     runtime.spawn(async move {
         loop {
-            let _ = tx.send(Command::Backlog).await;
-            tokio::time::sleep(Duration::from_secs(1)).await;
+            for printer in printers.iter() {
+                let _ = printer.tx.send(Command::Backlog).await;
+            }
+            tokio::time::sleep(poll_interval).await;
         }
     });
 
     println!("Running");
-    drawer.run()?;
+    done_rx.recv()??;
 
     Ok(())
 }
@@ -1,122 +1,562 @@
-use std::{fs, io::ErrorKind, path::PathBuf};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, bail};
+use anyhow::bail;
 use image::RgbaImage;
 use jiff::Timestamp;
+use serde::Serialize;
+use tokio::sync::mpsc;
 
-use crate::printer::Printer;
+use crate::{
+    archive::{Archive, Record},
+    drawer::Command,
+    jobs::{JobId, JobState, Jobs, PartialPrint},
+    printer::{Cut, Printer, PrinterConfig, PrinterStatus, Text},
+    printers::PrinterHandle,
+    queue::{Entry, EntryMeta, Queue},
+};
+
+/// What happened to an image handed to [`PersistentPrinter::print_image`].
+pub enum PrintOutcome {
+    Printed,
+    Queued,
+    Paused,
+}
+
+/// The result of the most recent printer status request.
+#[derive(Clone, Serialize)]
+pub struct StatusReport {
+    pub checked: Timestamp,
+    /// `None` if the printer couldn't be reached or doesn't report its status.
+    pub status: Option<PrinterStatus>,
+    pub error: Option<String>,
+}
 
 pub struct PersistentPrinter {
-    printer_file: Option<PathBuf>,
-    export_file: Option<PathBuf>,
-    queue_dir: PathBuf,
+    name: String,
+    config: PrinterConfig,
+    queue: Queue,
+    jobs: Jobs,
+    max_attempts: u32,
+    paused: Arc<AtomicBool>,
+    status: Arc<Mutex<Option<StatusReport>>>,
+    /// When the published status was requested from the printer.
+    status_checked: Option<Instant>,
+    archive: Option<Archive>,
+    /// Commands to the drawer driving this printer.
+    tx: mpsc::Sender<Command>,
 
     printer: Option<Printer>,
+    /// In batch mode, the cut after the most recently printed job.
+    pending_cut: Option<(Cut, Instant)>,
 }
 
 impl PersistentPrinter {
+    /// How long to wait for more jobs before cutting in batch mode.
+    const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// How long a status report is used before asking the printer again.
+    ///
+    /// Each status request consists of several round trips to the printer, so
+    /// it is not repeated for every job of a batch.
+    const STATUS_MAX_AGE: Duration = Duration::from_secs(2);
+
+    /// The queue, pause state, status and command channel are shared with the
+    /// handle.
     pub fn new(
-        printer_file: Option<PathBuf>,
-        export_file: Option<PathBuf>,
-        queue_dir: PathBuf,
+        config: PrinterConfig,
+        handle: &PrinterHandle,
+        jobs: Jobs,
+        max_attempts: u32,
+        archive: Option<Archive>,
     ) -> Self {
         Self {
-            printer_file,
-            export_file,
-            queue_dir,
+            name: handle.name.clone(),
+            config,
+            queue: handle.queue.clone(),
+            jobs,
+            max_attempts,
+            paused: handle.paused.clone(),
+            status: handle.status.clone(),
+            status_checked: None,
+            archive,
+            tx: handle.tx.clone(),
             printer: None,
+            pending_cut: None,
+        }
+    }
+
+    /// While paused, images are queued without trying to print them and the
+    /// backlog is not printed.
+    pub fn set_paused(&mut self, paused: bool) {
+        println!("{} printing", if paused { "Pausing" } else { "Resuming" });
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn request_status(&mut self) -> anyhow::Result<Option<PrinterStatus>> {
+        let Some(device) = &self.config.device else {
+            return Ok(None);
+        };
+        if !device.is_available() {
+            bail!("printer device not found");
+        }
+
+        if self.printer.is_none() {
+            self.reconnect_printer()?;
+        }
+        let Some(printer) = &self.printer else {
+            return Ok(None);
+        };
+
+        let result = printer.status();
+        if result.is_err() {
+            // Reconnect next time, the printer may have been replugged.
+            self.printer = None;
+        }
+        result
+    }
+
+    /// Ask the printer for its status and publish the result, unless the
+    /// published status is recent enough.
+    fn check_status(&mut self) -> StatusReport {
+        if let Some(checked) = self.status_checked
+            && checked.elapsed() < Self::STATUS_MAX_AGE
+            && let Some(report) = self.status.lock().unwrap().clone()
+        {
+            return report;
+        }
+
+        let result = self.request_status();
+        let report = StatusReport {
+            checked: Timestamp::now(),
+            status: result.as_ref().ok().cloned().flatten(),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+        };
+        *self.status.lock().unwrap() = Some(report.clone());
+        self.status_checked = Some(Instant::now());
+        report
+    }
+
+    /// Check whether the printer can't print right now, and why.
+    ///
+    /// Printers that don't answer status requests may still be able to print,
+    /// but a failed status request means that the printer can't be reached.
+    fn check_problems(&mut self) -> Option<String> {
+        let report = self.check_status();
+        if let Some(error) = report.error {
+            return Some(format!("Printer is unavailable: {error}"));
+        }
+        let status = report.status?;
+        if status.can_print() {
+            return None;
         }
+        Some(format!(
+            "Printer can't print: {}",
+            status.problems().join(", ")
+        ))
     }
 
-    fn print_image_immediately(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
+    fn print_image_immediately(
+        &mut self,
+        image: &RgbaImage,
+        printed_rows: &mut u32,
+    ) -> anyhow::Result<()> {
         let Some(printer) = &mut self.printer else {
             bail!("no printer found");
         };
-        printer.print_image(image)?;
+        printer.print_image(image, printed_rows)?;
+        Ok(())
+    }
+
+    fn print_text_immediately(&mut self, text: &Text) -> anyhow::Result<()> {
+        let Some(printer) = &mut self.printer else {
+            bail!("no printer found");
+        };
+        printer.print_text(text)?;
         Ok(())
     }
 
     fn reconnect_printer(&mut self) -> anyhow::Result<()> {
-        let printer = Printer::new(self.printer_file.clone(), self.export_file.clone())?;
+        // Whatever made reconnecting necessary may have changed the status.
+        self.status_checked = None;
+        let printer = Printer::new(&self.config)?;
         self.printer = Some(printer);
         Ok(())
     }
 
-    fn print_image_robustly(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
+    /// Like [`Self::print_image_immediately`], but remembers in the job if the
+    /// attempt failed after printing part of the image.
+    fn print_image_attempt(
+        &mut self,
+        job: Option<JobId>,
+        image: &RgbaImage,
+        printed_rows: &mut u32,
+    ) -> anyhow::Result<()> {
+        let start = *printed_rows;
+        let Err(err) = self.print_image_immediately(image, printed_rows) else {
+            return Ok(());
+        };
+        if *printed_rows == start {
+            return Err(err);
+        }
+
+        let total_rows = image.height();
+        println!("Failed after printing {printed_rows} of {total_rows} rows");
+        if let Some(job) = job {
+            let partial_print = PartialPrint {
+                time: Timestamp::now(),
+                printed_rows: *printed_rows,
+                total_rows,
+                error: format!("{err:#}"),
+            };
+            self.jobs.add_partial_print(job, partial_print);
+        }
+        Err(err)
+    }
+
+    /// Print the rows of an image below `printed_rows`, reconnecting and
+    /// retrying once if that fails.
+    ///
+    /// `printed_rows` is advanced as the image is printed, so the retry and
+    /// any later attempts continue where the previous attempt stopped.
+    fn print_image_robustly(
+        &mut self,
+        job: Option<JobId>,
+        image: &RgbaImage,
+        printed_rows: &mut u32,
+    ) -> anyhow::Result<()> {
         println!("Printing image");
-        if self.print_image_immediately(image).is_ok() {
+        if self.print_image_attempt(job, image, printed_rows).is_ok() {
             return Ok(());
         }
         println!("First attempt failed, reconnecting and retrying");
         self.reconnect_printer()?;
-        self.print_image_immediately(image)?;
+        self.print_image_attempt(job, image, printed_rows)?;
         Ok(())
     }
 
-    fn enqueue_image(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
-        let now = Timestamp::now();
-        let path = self.queue_dir.join(format!("{now}.png"));
-        println!("Enqueuing image {}", path.display());
+    /// Cut the paper after a job, or remember to do so later in batch mode.
+    ///
+    /// The job has already been printed at this point, so failing to cut
+    /// doesn't count as an error.
+    fn cut_after_job(&mut self, cut: Option<Cut>) {
+        let cut = cut.unwrap_or(self.config.cut);
+        if self.config.cut_batch {
+            // A job that shouldn't be cut doesn't affect earlier jobs.
+            if cut != Cut::None {
+                self.pending_cut = Some((cut, Instant::now()));
+                self.schedule_backlog(Self::BATCH_TIMEOUT);
+            }
+            return;
+        }
+        self.cut(cut);
+    }
+
+    /// Print the backlog after a delay, so the drawer doesn't have to wait
+    /// for the next poll.
+    fn schedule_backlog(&self, delay: Duration) {
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let _ = tx.blocking_send(Command::Backlog);
+        });
+    }
 
-        fs::create_dir_all(&self.queue_dir)
-            .with_context(|| format!("At {}", self.queue_dir.display()))
-            .context("Failed to create queue directory")?;
+    fn cut(&mut self, cut: Cut) {
+        if cut == Cut::None {
+            return;
+        }
+
+        println!("Cutting paper");
+        let Some(printer) = &mut self.printer else {
+            return;
+        };
+        if let Err(err) = printer.cut(cut, self.config.cut_feed) {
+            println!("Failed to cut paper: {err:#}");
+        }
+    }
 
-        image
-            .save(&path)
-            .with_context(|| format!("At {}", path.display()))
-            .context("Failed to save image to queue")?;
+    /// Cut after the last job of a batch once no jobs have been printed for a
+    /// while.
+    fn finish_batch(&mut self) {
+        if let Some((cut, printed)) = self.pending_cut
+            && printed.elapsed() >= Self::BATCH_TIMEOUT
+        {
+            self.pending_cut = None;
+            self.cut(cut);
+        }
+    }
 
-        Ok(())
+    pub fn is_archiving(&self) -> bool {
+        self.archive.is_some()
     }
 
-    pub fn print_image(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
-        if self.print_image_robustly(image).is_err() {
-            self.enqueue_image(image)?;
+    /// Record a printed image in the archive, if there is one.
+    ///
+    /// The image has already been printed at this point, so failing to archive
+    /// it doesn't count as an error.
+    fn archive(
+        &self,
+        image: &RgbaImage,
+        job: Option<JobId>,
+        document: String,
+        params: serde_json::Value,
+        submitter: Option<String>,
+    ) {
+        let Some(archive) = &self.archive else {
+            return;
+        };
+
+        let record = Record {
+            job,
+            document,
+            params,
+            submitter,
+            printer: self.name.clone(),
+            printed: Timestamp::now(),
+            width: image.width(),
+            height: image.height(),
+            length_mm: image.height() as f32 / Printer::DOTS_PER_MM,
+        };
+
+        if let Err(err) = archive.push(image, &record) {
+            println!("Failed to archive image: {err:#}");
         }
+    }
+
+    /// Archive an image printed for a job.
+    pub fn archive_job(&self, id: JobId, image: &RgbaImage) {
+        let (document, params, submitter) = match self.jobs.get(id) {
+            Some(job) => (job.document, job.params, job.submitter),
+            None => ("unknown".to_string(), serde_json::Value::Null, None),
+        };
+        self.archive(image, Some(id), document, params, submitter);
+    }
+
+    fn enqueue_image(
+        &mut self,
+        id: JobId,
+        image: &RgbaImage,
+        printed_rows: u32,
+        attempts: u32,
+        error: Option<String>,
+    ) -> anyhow::Result<()> {
+        let job = self.jobs.get(id);
+        let queued = Timestamp::now();
+
+        let meta = EntryMeta {
+            job: Some(id),
+            document: job
+                .as_ref()
+                .map(|it| it.document.clone())
+                .unwrap_or_else(|| "unknown".to_string()),
+            params: job.as_ref().map(|it| it.params.clone()).unwrap_or_default(),
+            cut: job.as_ref().and_then(|it| it.cut),
+            submitter: job.and_then(|it| it.submitter),
+            queued,
+            order: queued.as_millisecond(),
+            attempts,
+            printed_rows,
+            error,
+            last_error: None,
+        };
+
+        self.queue.push(image, &meta)?;
         Ok(())
     }
 
-    pub fn print_backlog(&mut self) -> anyhow::Result<()> {
-        // Don't try to print if the chances of success are zero.
-        if let Some(file) = &self.printer_file
-            && !file.exists()
-        {
-            return Ok(());
+    pub fn print_image(&mut self, id: JobId, image: &RgbaImage) -> anyhow::Result<PrintOutcome> {
+        if self.is_paused() {
+            self.enqueue_image(id, image, 0, 0, None)?;
+            return Ok(PrintOutcome::Paused);
         }
 
-        let mut files = vec![];
+        if let Some(error) = self.check_problems() {
+            self.enqueue_image(id, image, 0, 0, Some(error))?;
+            return Ok(PrintOutcome::Queued);
+        }
 
-        match self.queue_dir.read_dir() {
-            Err(err) if err.kind() == ErrorKind::NotFound => {}
+        // Jobs are printed in the order they were submitted in, so earlier
+        // jobs that are still queued must be printed first.
+        if !self.queue.is_empty()? && !self.drain_backlog() {
+            self.enqueue_image(id, image, 0, 0, None)?;
+            return Ok(PrintOutcome::Queued);
+        }
+
+        let mut printed_rows = 0;
+        if let Err(err) = self.print_image_robustly(Some(id), image, &mut printed_rows) {
+            self.enqueue_image(id, image, printed_rows, 0, Some(format!("{err:#}")))?;
+            return Ok(PrintOutcome::Queued);
+        }
+
+        self.archive_job(id, image);
+        self.cut_after_job(self.jobs.get(id).and_then(|it| it.cut));
+        Ok(PrintOutcome::Printed)
+    }
+
+    /// Print text using the printer's built-in font.
+    ///
+    /// Text can't be queued, so this returns `Ok(false)` without printing
+    /// anything if printing is paused, no printer is configured or earlier
+    /// jobs can't be printed first. The caller should fall back to printing
+    /// the text as an image in that case.
+    pub fn print_text(&mut self, id: JobId, text: &Text) -> anyhow::Result<bool> {
+        if self.is_paused() || self.config.device.is_none() {
+            return Ok(false);
+        }
 
-            Err(err) => Err(err)
-                .with_context(|| format!("At {}", self.queue_dir.display()))
-                .context("Failed to open queue dir")?,
+        if let Some(error) = self.check_problems() {
+            println!("{error}");
+            return Ok(false);
+        }
+
+        // Earlier jobs that are still queued must be printed first.
+        if !self.queue.is_empty()? && !self.drain_backlog() {
+            return Ok(false);
+        }
+
+        println!("Printing text");
+        if self.print_text_immediately(text).is_err() {
+            println!("First attempt failed, reconnecting and retrying");
+            self.reconnect_printer()?;
+            self.print_text_immediately(text)?;
+        }
+
+        self.cut_after_job(self.jobs.get(id).and_then(|it| it.cut));
+        Ok(true)
+    }
 
-            Ok(dir) => {
-                for entry in dir {
-                    let entry = entry?;
-                    if entry.file_type()?.is_file() {
-                        files.push(entry.path());
-                    }
-                }
+    /// Print as many queued images as possible.
+    ///
+    /// Returns whether the queue was worked through completely, i.e. whether
+    /// new jobs can be printed without skipping ahead of earlier ones.
+    pub fn print_backlog(&mut self) -> bool {
+        // The status is checked even while paused to keep it up to date.
+        let problems = self.check_problems();
+
+        if self.is_paused() || problems.is_some() {
+            return false;
+        }
+
+        self.drain_backlog()
+    }
+
+    /// Like [`Self::print_backlog`], but without checking whether the printer
+    /// can print, because the caller just did.
+    fn drain_backlog(&mut self) -> bool {
+        self.finish_batch();
+
+        let entries = match self.queue.entries() {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("Failed to list queued images: {err:#}");
+                return false;
+            }
+        };
+
+        for entry in entries {
+            // A broken entry must not prevent the others from being printed.
+            match self.print_entry(entry) {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(err) => println!("Failed to handle queued image: {err:#}"),
             }
         }
 
-        files.sort_unstable();
+        true
+    }
+
+    /// Print a single queued image, returning whether the printer still works.
+    fn print_entry(&mut self, entry: Entry) -> anyhow::Result<bool> {
+        let Entry { id, mut meta } = entry;
+
+        // The entry may have been removed via the API in the meantime.
+        if !self.queue.contains(&id) {
+            return Ok(true);
+        }
+
+        println!("Dequeuing image {id}");
 
-        for file in files {
-            println!("Dequeuing image {}", file.display());
-            let image: RgbaImage = image::open(&file)?.into_rgba8();
-            if self.print_image_robustly(&image).is_err() {
-                return Ok(());
+        let image = match self.load_queued_image(&id) {
+            Ok(image) => image,
+            Err(err) => {
+                self.fail_attempt(&id, meta, err)?;
+                return Ok(true);
             }
-            fs::remove_file(&file)
-                .with_context(|| format!("At {}", file.display()))
-                .context("Failed to remove printed image")?;
+        };
+
+        // The image will be printed once the printer works again, so failures
+        // of the printer don't count as failed attempts.
+        if let Err(err) = self.print_image_robustly(meta.job, &image, &mut meta.printed_rows) {
+            meta.last_error = Some(format!("{err:#}"));
+            self.queue.update_meta(&id, &meta)?;
+            return Ok(false);
+        }
+
+        // From here on, the image has been printed, even if the entry has been
+        // removed via the API while printing.
+        if let Some(job) = meta.job {
+            self.jobs.set_state(job, JobState::Printed);
+        }
+        let EntryMeta {
+            job,
+            document,
+            params,
+            submitter,
+            ..
+        } = meta.clone();
+        self.archive(&image, job, document, params, submitter);
+        self.cut_after_job(meta.cut);
+        self.queue.remove(&id)?;
+
+        Ok(true)
+    }
+
+    /// Load a queued image and make sure it can be printed at all.
+    fn load_queued_image(&self, id: &str) -> anyhow::Result<RgbaImage> {
+        let image = self.queue.load_image(id)?;
+        if image.width() != self.config.width {
+            bail!(
+                "image is {} pixels wide but the printer expects {}",
+                image.width(),
+                self.config.width
+            );
         }
+        Ok(image)
+    }
 
+    /// Count a failed attempt caused by the queued image itself, and give up
+    /// on the image after too many attempts.
+    fn fail_attempt(
+        &self,
+        id: &str,
+        mut meta: EntryMeta,
+        err: anyhow::Error,
+    ) -> anyhow::Result<()> {
+        meta.attempts += 1;
+        meta.last_error = Some(format!("{err:#}"));
+
+        if meta.attempts < self.max_attempts {
+            self.queue.update_meta(id, &meta)?;
+            return Ok(());
+        }
+
+        println!("Giving up on image {id} after {} attempts", meta.attempts);
+        self.queue.bury(id, &meta)?;
+        if let Some(job) = meta.job {
+            let error = format!("{err:#}");
+            self.jobs.set_state(job, JobState::Failed { error });
+        }
         Ok(())
     }
 }
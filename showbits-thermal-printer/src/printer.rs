@@ -1,19 +1,89 @@
-use std::path::PathBuf;
+mod conversion;
+mod device;
+mod driver;
+mod emulator;
+mod raster;
+mod status;
+mod text;
 
-use anyhow::Context;
+use std::{borrow::Cow, path::PathBuf};
+
+use anyhow::{Context, bail};
+use clap::ValueEnum;
 use escpos::{
-    driver::FileDriver,
+    driver::Driver,
     printer::Printer as EPrinter,
     printer_options::PrinterOptions,
     utils::{GS, PageCode, Protocol},
 };
-use image::{Rgba, RgbaImage};
+use image::{Rgba, RgbaImage, imageops};
+use serde::{Deserialize, Serialize};
+
+use self::{conversion::Converter, driver::DeviceDriver};
+
+pub use self::{
+    conversion::{Conversion, Weighting},
+    device::Device,
+    raster::RasterMode,
+    status::PrinterStatus,
+    text::{Align, Text},
+};
+
+/// Where and how images are printed.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PrinterConfig {
+    pub device: Option<Device>,
+    /// Width of the printable area in pixels, must be a multiple of 8.
+    #[serde(default = "PrinterConfig::default_width")]
+    pub width: u32,
+    /// Export an image of whatever is printed here.
+    pub export: Option<PathBuf>,
+    #[serde(default)]
+    pub raster: RasterMode,
+    /// How pixels are converted into black and white dots.
+    #[serde(default)]
+    pub conversion: Conversion,
+    /// How to cut the paper after each job.
+    #[serde(default)]
+    pub cut: Cut,
+    /// Additional distance to feed the paper before cutting, in dots.
+    #[serde(default)]
+    pub cut_feed: u8,
+    /// Only cut once no jobs have been printed for a while.
+    #[serde(default)]
+    pub cut_batch: bool,
+}
+
+impl PrinterConfig {
+    fn default_width() -> u32 {
+        Printer::DEFAULT_WIDTH
+    }
+}
 
-use crate::color;
+/// How the printer's auto-cutter cuts the paper.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cut {
+    /// Don't cut, e.g. because the printer has no auto-cutter.
+    #[default]
+    None,
+    /// Leave a bit of paper uncut in the middle.
+    Partial,
+    Full,
+}
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
 
 pub struct Printer {
-    printer: Option<EPrinter<FileDriver>>,
+    printer: Option<EPrinter<DeviceDriver>>,
+    /// Shares its device with the driver inside `printer`.
+    driver: Option<DeviceDriver>,
     export_path: Option<PathBuf>,
+    raster: RasterMode,
+    conversion: Conversion,
+    width: u32,
 }
 
 impl Printer {
@@ -24,45 +94,55 @@ impl Printer {
     /// <https://download4.epson.biz/sec_pubs/pos/reference_en/charcode/page_00.html>
     const PAGE_CODE: PageCode = PageCode::PC437;
 
-    /// Width of the printable area in pixels.
+    /// Width of the printable area of 58 mm printers in pixels.
     ///
-    /// Assumed to be a multiple of 8, then measured to that precision.
-    pub const WIDTH: u32 = 8 * 48;
+    /// Assumed to be a multiple of 8, then measured to that precision. 80 mm
+    /// printers usually have a width of 576 pixels.
+    pub const DEFAULT_WIDTH: u32 = 8 * 48;
 
-    /// Images are printed in chunks because a single print command can only
-    /// print so much data.
-    ///
-    /// Looking at the [epson docs][0], most printers seem to support a max
-    /// height of 2303, though some go up to 4095. Because I don't want to waste
-    /// a bunch of paper trying various different heights, I'll go with 1023
-    /// because it's nice and round and slightly conservative.
-    ///
-    /// [0]: https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lv_0.html
-    const CHUNK_HEIGHT: u32 = 0b0000_0011_1111_1111;
-
-    pub fn new(
-        printer_path: Option<PathBuf>,
-        export_path: Option<PathBuf>,
-    ) -> anyhow::Result<Self> {
-        let printer = if let Some(path) = printer_path {
-            let driver = FileDriver::open(&path)
-                .with_context(|| format!("At {}", path.display()))
+    /// Resolution of the printer, which is 203 dpi for most thermal printers.
+    pub const DOTS_PER_MM: f32 = 8.0;
+
+    pub fn new(config: &PrinterConfig) -> anyhow::Result<Self> {
+        let (printer, driver) = if let Some(device) = &config.device {
+            let driver = DeviceDriver::open(device, config.width)
+                .with_context(|| format!("At {device}"))
                 .context("Failed to open printer driver")?;
             let protocol = Protocol::default();
             let mut options = PrinterOptions::default();
             options.page_code(Some(Self::PAGE_CODE));
-            Some(EPrinter::new(driver, protocol, Some(options)))
+            let printer = EPrinter::new(driver.clone(), protocol, Some(options));
+            (Some(printer), Some(driver))
         } else {
-            None
+            (None, None)
         };
 
         Ok(Self {
             printer,
-            export_path,
+            driver,
+            export_path: config.export.clone(),
+            raster: config.raster,
+            conversion: config.conversion,
+            width: config.width,
         })
     }
 
-    pub fn print_image(&mut self, image: &RgbaImage) -> anyhow::Result<()> {
+    /// Ask the printer for its status.
+    ///
+    /// Returns `None` if there is no printer or it doesn't report its status.
+    pub fn status(&self) -> anyhow::Result<Option<PrinterStatus>> {
+        let Some(driver) = &self.driver else {
+            return Ok(None);
+        };
+        PrinterStatus::request_all(driver).context("Failed to request printer status")
+    }
+
+    /// Print an image, skipping its first `printed_rows` rows.
+    ///
+    /// The image is sent in chunks, and `printed_rows` is advanced after each
+    /// chunk that was sent successfully. If printing fails, it can thus be
+    /// resumed without printing the top of the image a second time.
+    pub fn print_image(&mut self, image: &RgbaImage, printed_rows: &mut u32) -> anyhow::Result<()> {
         if let Some(path) = &self.export_path {
             image
                 .save(path)
@@ -70,82 +150,217 @@ impl Printer {
                 .context("Failed to export to-be-printed image")?;
         }
 
+        if image.width() != self.width {
+            bail!(
+                "image is {} pixels wide but the printer expects {}",
+                image.width(),
+                self.width
+            );
+        }
+
+        if let (Some(printer), Some(driver)) = (&mut self.printer, &self.driver) {
+            let remaining = match *printed_rows {
+                0 => Cow::Borrowed(image),
+                rows => Cow::Owned(Self::remaining_rows(image, rows)),
+            };
+            printer.init()?;
+            for chunk in self.raster.encode(&remaining, &self.conversion)? {
+                printer.custom(&chunk.commands)?;
+                printer.print().context("Failed to print image")?;
+                driver.flush().context("Failed to flush printer driver")?;
+                *printed_rows += chunk.height;
+            }
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// The part of the image below its first `printed_rows` rows.
+    pub fn remaining_rows(image: &RgbaImage, printed_rows: u32) -> RgbaImage {
+        let printed_rows = printed_rows.min(image.height());
+        let height = image.height() - printed_rows;
+        imageops::crop_imm(image, 0, printed_rows, image.width(), height).to_image()
+    }
+
+    pub fn print_text(&mut self, text: &Text) -> anyhow::Result<()> {
         if let Some(printer) = &mut self.printer {
-            Self::print_image_to_printer(printer, image).context("Failed to print image")?;
+            printer.init()?;
+            printer.custom(&text.encode())?;
+            printer.print().context("Failed to print text")?;
+            self.flush()?;
         }
 
         Ok(())
     }
 
-    /// Uses the obsolete `GS v 0` command to print an image.
+    /// Feed the paper to the cutter and cut it.
     ///
-    /// The image is printed in chunks because the command used has a maximum
-    /// amount of data it can handle. In-between chunks, the paper is not moved,
-    /// meaning that chunks connect to each other seamlessly.
+    /// The printer feeds the paper to the cutting position by itself, `feed`
+    /// is the additional distance in dots.
     ///
-    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lv_0.html>
-    fn print_image_to_printer(
-        printer: &mut EPrinter<FileDriver>,
-        image: &RgbaImage,
-    ) -> anyhow::Result<()> {
-        assert_eq!(Self::WIDTH % 8, 0);
-        assert_eq!(image.width(), Self::WIDTH);
-
-        printer.init()?;
-
-        for y_offset in (0..image.height()).step_by(Self::CHUNK_HEIGHT as usize) {
-            // The command takes the width in bytes (groups of 8 pixels) and the
-            // height in pixels. Both are then split into two bytes and sent.
-            let chunk_width = Self::WIDTH / 8;
-            let chunk_height = Self::CHUNK_HEIGHT.min(image.height() - y_offset);
-
-            let m = 0; // Normal resolution
-            let [_, _, x_h, x_l] = chunk_width.to_be_bytes();
-            let [_, _, y_h, y_l] = chunk_height.to_be_bytes();
-            let mut command = vec![GS, b'v', b'0', m, x_l, x_h, y_l, y_h];
-
-            for y in y_offset..y_offset + chunk_height {
-                for x in (0..Self::WIDTH).step_by(8) {
-                    command.push(Self::get_horizontal_byte_starting_at(image, x, y));
-                }
-            }
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_cv.html>
+    pub fn cut(&mut self, cut: Cut, feed: u8) -> anyhow::Result<()> {
+        let m = match cut {
+            Cut::None => return Ok(()),
+            Cut::Full => 65,
+            Cut::Partial => 66,
+        };
 
-            printer.custom(&command)?;
+        if let Some(printer) = &mut self.printer {
+            printer.custom(&[GS, b'V', m, feed])?;
+            printer.print().context("Failed to cut paper")?;
+            self.flush()?;
         }
 
-        printer.print()?;
         Ok(())
     }
 
-    fn get_horizontal_byte_starting_at(image: &RgbaImage, x: u32, y: u32) -> u8 {
-        let p7 = Self::pixel_to_bit(*image.get_pixel(x, y));
-        let p6 = Self::pixel_to_bit(*image.get_pixel(x + 1, y));
-        let p5 = Self::pixel_to_bit(*image.get_pixel(x + 2, y));
-        let p4 = Self::pixel_to_bit(*image.get_pixel(x + 3, y));
-        let p3 = Self::pixel_to_bit(*image.get_pixel(x + 4, y));
-        let p2 = Self::pixel_to_bit(*image.get_pixel(x + 5, y));
-        let p1 = Self::pixel_to_bit(*image.get_pixel(x + 6, y));
-        let p0 = Self::pixel_to_bit(*image.get_pixel(x + 7, y));
+    /// Make sure everything has actually been sent to the printer.
+    fn flush(&self) -> anyhow::Result<()> {
+        if let Some(driver) = &self.driver {
+            driver.flush().context("Failed to flush printer driver")?;
+            driver.save_capture()?;
+        }
+        Ok(())
+    }
 
-        let b7 = if p7 { 0b1000_0000 } else { 0 };
-        let b6 = if p6 { 0b0100_0000 } else { 0 };
-        let b5 = if p5 { 0b0010_0000 } else { 0 };
-        let b4 = if p4 { 0b0001_0000 } else { 0 };
-        let b3 = if p3 { 0b0000_1000 } else { 0 };
-        let b2 = if p2 { 0b0000_0100 } else { 0 };
-        let b1 = if p1 { 0b0000_0010 } else { 0 };
-        let b0 = if p0 { 0b0000_0001 } else { 0 };
+    /// The rows' pixels as horizontal bytes, MSB first, with 1 meaning black.
+    ///
+    /// This works directly on the image's raw buffer, which is much faster
+    /// than looking up each pixel individually.
+    ///
+    /// The image's width must be a multiple of 8.
+    fn get_horizontal_bytes(
+        image: &RgbaImage,
+        converter: &Converter,
+        y_offset: u32,
+        height: u32,
+    ) -> Vec<u8> {
+        let row_len = image.width() as usize * 4;
+        let start = y_offset as usize * row_len;
+        let end = start + height as usize * row_len;
 
-        b7 + b6 + b5 + b4 + b3 + b2 + b1 + b0
+        image.as_raw()[start..end]
+            .chunks_exact(8 * 4)
+            .map(|pixels| {
+                pixels.chunks_exact(4).fold(0, |byte, pixel| {
+                    (byte << 1) | converter.is_black(pixel) as u8
+                })
+            })
+            .collect()
     }
 
-    /// Convert pixel to bit, `true` is black and `false` is white.
+    /// The 8 pixels below and including `(x, y)` as a vertical byte, MSB at the
+    /// top. Pixels at or below `y_end` are white.
+    fn get_vertical_byte_starting_at(
+        image: &RgbaImage,
+        converter: &Converter,
+        x: u32,
+        y: u32,
+        y_end: u32,
+    ) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            let black = y + i < y_end && converter.is_black(&image.get_pixel(x, y + i).0);
+            if black {
+                byte |= 0b1000_0000 >> i;
+            }
+        }
+        byte
+    }
+
+    /// Convert an image to pure black and white, exactly like it would appear
+    /// on paper.
+    pub fn threshold(image: &RgbaImage, conversion: &Conversion) -> RgbaImage {
+        let converter = conversion.converter();
+        RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+            match converter.is_black(&image.get_pixel(x, y).0) {
+                true => BLACK,
+                false => WHITE,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage, imageops};
+
+    use crate::color;
+
+    use super::{Conversion, Printer};
+
+    /// The conversion the printer originally used, which the default
+    /// conversion must reproduce exactly.
     ///
-    /// Instead of doing the physically accurate thing, I do what makes the most
-    /// sense visually.
+    /// Instead of doing the physically accurate thing, it does what makes the
+    /// most sense visually.
     fn pixel_to_bit(pixel: Rgba<u8>) -> bool {
         let color = color::image_to_palette(pixel);
         let avg = (color.red + color.green + color.blue) / 3.0;
         avg < 0.5 // true == black
     }
+
+    fn reference_bytes(image: &RgbaImage) -> Vec<u8> {
+        let mut bytes = vec![];
+        for y in 0..image.height() {
+            for x in (0..image.width()).step_by(8) {
+                let byte = (x..x + 8).fold(0, |byte, x| {
+                    (byte << 1) | pixel_to_bit(*image.get_pixel(x, y)) as u8
+                });
+                bytes.push(byte);
+            }
+        }
+        bytes
+    }
+
+    fn horizontal_bytes(image: &RgbaImage) -> Vec<u8> {
+        let converter = Conversion::default().converter();
+        Printer::get_horizontal_bytes(image, &converter, 0, image.height())
+    }
+
+    #[test]
+    fn every_color() {
+        // Every 61st RGB color, with varying transparency. Checking all of them
+        // takes too long in debug builds.
+        let image = RgbaImage::from_fn(512, 512, |x, y| {
+            let [_, r, g, b] = ((y * 512 + x) * 61).to_be_bytes();
+            Rgba([r, g, b, r ^ g ^ b])
+        });
+        assert!(horizontal_bytes(&image) == reference_bytes(&image));
+    }
+
+    #[test]
+    fn real_images() {
+        for bytes in [
+            &include_bytes!("documents/cells/image.png")[..],
+            &include_bytes!("documents/image/image.png")[..],
+            &include_bytes!("documents/xkcd/image.png")[..],
+        ] {
+            let image = image::load_from_memory(bytes).unwrap().into_rgba8();
+            let width = image.width() / 8 * 8;
+            let image = imageops::crop_imm(&image, 0, 0, width, image.height()).to_image();
+            assert!(horizontal_bytes(&image) == reference_bytes(&image));
+        }
+    }
+
+    #[test]
+    fn thresholds() {
+        for threshold in [0.0, 0.1, 0.2, 0.25, 1.0 / 3.0, 0.4, 0.5, 0.6, 0.7, 0.9, 1.0] {
+            let converter = Conversion {
+                threshold,
+                ..Conversion::default()
+            }
+            .converter();
+            for sum in 0..=765_u32 {
+                let r = sum.min(255);
+                let g = (sum - r).min(255);
+                let b = sum - r - g;
+                let pixel = [r as u8, g as u8, b as u8, 255];
+                let expected = (sum as f64 / 765.0) < threshold;
+                assert_eq!(converter.is_black(&pixel), expected, "{threshold} {sum}");
+            }
+        }
+    }
 }
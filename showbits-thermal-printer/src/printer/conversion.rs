@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// How the color channels of a pixel are combined into its brightness.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weighting {
+    /// All channels count the same.
+    #[default]
+    Average,
+    /// Green counts the most and blue the least, roughly like the human eye
+    /// perceives them.
+    ///
+    /// <https://en.wikipedia.org/wiki/Rec._709#Luma_coefficients>
+    Rec709,
+}
+
+impl Weighting {
+    fn weights(self) -> [f32; 3] {
+        match self {
+            Self::Average => [1.0 / 3.0; 3],
+            Self::Rec709 => [0.2126, 0.7152, 0.0722],
+        }
+    }
+}
+
+/// How pixels are converted into black and white dots.
+///
+/// Each sRGB channel is raised to the power of the gamma, then the channels
+/// are combined into the pixel's brightness. Pixels darker than the threshold
+/// become black dots. The defaults reproduce the original fixed conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Conversion {
+    /// Brightness between 0 and 1 below which pixels are black.
+    pub threshold: f64,
+    /// Values above 1 make gray pixels darker and thus anti-aliased lines
+    /// thicker, values below 1 make them lighter and thinner.
+    pub gamma: f64,
+    pub weighting: Weighting,
+    /// Composite transparent pixels onto white paper instead of ignoring their
+    /// alpha channel.
+    pub alpha: bool,
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            gamma: 1.0,
+            weighting: Weighting::default(),
+            alpha: false,
+        }
+    }
+}
+
+impl Conversion {
+    /// Precompute everything needed to convert pixels quickly.
+    pub fn converter(&self) -> Converter {
+        // With a plain average and no gamma, a pixel's brightness only depends
+        // on the sum of its channels, so the result for every sum can be
+        // looked up instead. Multiplying the threshold instead of dividing the
+        // sum would be off by one whenever the product isn't exact. For the
+        // default threshold, this gives exactly the same result for every
+        // possible color as the original conversion, including its floating
+        // point rounding (see the printer's tests).
+        let black_sums = (self.weighting == Weighting::Average && self.gamma == 1.0)
+            .then(|| std::array::from_fn(|sum| (sum as f64 / 765.0) < self.threshold));
+
+        Converter {
+            black_sums,
+            curve: std::array::from_fn(|c| (c as f64 / 255.0).powf(self.gamma) as f32),
+            weights: self.weighting.weights(),
+            threshold: self.threshold as f32,
+            alpha: self.alpha,
+        }
+    }
+}
+
+/// A [`Conversion`] prepared for converting many pixels.
+pub struct Converter {
+    /// If set, whether pixels whose channels add up to the index are black.
+    black_sums: Option<[bool; 3 * 255 + 1]>,
+    /// The brightness of each channel value after applying the gamma.
+    curve: [f32; 256],
+    weights: [f32; 3],
+    threshold: f32,
+    alpha: bool,
+}
+
+impl Converter {
+    /// Whether a pixel, given as its raw RGBA bytes, is printed black.
+    pub fn is_black(&self, pixel: &[u8]) -> bool {
+        let &[r, g, b, a] = pixel else {
+            unreachable!("pixels consist of four bytes");
+        };
+
+        let [r, g, b] = if self.alpha && a < 255 {
+            let a = a as u16;
+            [r, g, b].map(|c| ((c as u16 * a + 255 * (255 - a) + 127) / 255) as u8)
+        } else {
+            [r, g, b]
+        };
+
+        if let Some(black_sums) = &self.black_sums {
+            return black_sums[r as usize + g as usize + b as usize];
+        }
+
+        let [wr, wg, wb] = self.weights;
+        let brightness =
+            wr * self.curve[r as usize] + wg * self.curve[g as usize] + wb * self.curve[b as usize];
+        brightness < self.threshold
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+/// Where the printer is connected.
+///
+/// Specified as a URI like `file:///dev/usb/lp0`, `tcp://host:9100` or
+/// `serial:///dev/ttyUSB0?baud=19200`. Plain paths are treated as files.
+///
+/// For testing, `virtual:///tmp/printed.png` is a virtual printer that saves
+/// the commands it receives and an image of what it would have printed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Device {
+    File(PathBuf),
+    Tcp(String),
+    Serial { path: PathBuf, baud: u32 },
+    Virtual(PathBuf),
+}
+
+impl Device {
+    /// The raw port used by most networked ESC/POS printers.
+    const DEFAULT_TCP_PORT: u16 = 9100;
+
+    const DEFAULT_BAUD: u32 = 9600;
+
+    /// Whether it makes sense to try connecting to the device.
+    ///
+    /// Network printers can't be checked without connecting to them.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Self::File(path) => path.exists(),
+            Self::Tcp(_) => true,
+            Self::Serial { path, .. } => path.exists(),
+            Self::Virtual(_) => true,
+        }
+    }
+
+    /// The file that appears when the printer is connected, if any.
+    pub fn watch_path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) | Self::Serial { path, .. } => Some(path),
+            Self::Tcp(_) | Self::Virtual(_) => None,
+        }
+    }
+}
+
+impl FromStr for Device {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = s.split_once("://") else {
+            return Ok(Self::File(s.into()));
+        };
+
+        match scheme {
+            "file" => Ok(Self::File(rest.into())),
+
+            "tcp" => {
+                let addr = rest.trim_end_matches('/');
+                if addr.is_empty() {
+                    bail!("missing host in {s:?}");
+                }
+                // IPv6 addresses must be in brackets, e.g. `tcp://[::1]:9100`.
+                let has_port = addr.rsplit_once(':').is_some_and(|(host, port)| {
+                    port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+                });
+                if has_port {
+                    Ok(Self::Tcp(addr.to_string()))
+                } else {
+                    Ok(Self::Tcp(format!("{addr}:{}", Self::DEFAULT_TCP_PORT)))
+                }
+            }
+
+            "serial" => {
+                let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+                let mut baud = Self::DEFAULT_BAUD;
+                for param in query.split('&').filter(|it| !it.is_empty()) {
+                    match param.split_once('=') {
+                        Some(("baud", value)) => {
+                            baud = value
+                                .parse()
+                                .with_context(|| format!("Invalid baud rate {value:?}"))?;
+                        }
+                        _ => bail!("unknown parameter {param:?} in {s:?}"),
+                    }
+                }
+                Ok(Self::Serial {
+                    path: path.into(),
+                    baud,
+                })
+            }
+
+            "virtual" => Ok(Self::Virtual(rest.into())),
+
+            _ => bail!("unknown scheme {scheme:?} in {s:?}"),
+        }
+    }
+}
+
+impl TryFrom<String> for Device {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file://{}", path.display()),
+            Self::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Self::Serial { path, baud } => write!(f, "serial://{}?baud={baud}", path.display()),
+            Self::Virtual(path) => write!(f, "virtual://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Device;
+
+    fn parse(s: &str) -> String {
+        s.parse::<Device>().unwrap().to_string()
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(parse("/dev/usb/lp0"), "file:///dev/usb/lp0");
+        assert_eq!(parse("file:///dev/usb/lp0"), "file:///dev/usb/lp0");
+        assert_eq!(parse("virtual:///tmp/out.png"), "virtual:///tmp/out.png");
+    }
+
+    #[test]
+    fn tcp() {
+        assert_eq!(parse("tcp://printer"), "tcp://printer:9100");
+        assert_eq!(parse("tcp://printer/"), "tcp://printer:9100");
+        assert_eq!(parse("tcp://printer:1234"), "tcp://printer:1234");
+        assert_eq!(parse("tcp://10.0.0.5"), "tcp://10.0.0.5:9100");
+        assert_eq!(parse("tcp://10.0.0.5:1234"), "tcp://10.0.0.5:1234");
+    }
+
+    #[test]
+    fn tcp_ipv6() {
+        assert_eq!(parse("tcp://[::1]"), "tcp://[::1]:9100");
+        assert_eq!(parse("tcp://[::1]:1234"), "tcp://[::1]:1234");
+        assert_eq!(parse("tcp://[fe80::1]/"), "tcp://[fe80::1]:9100");
+    }
+
+    #[test]
+    fn serial() {
+        assert_eq!(
+            parse("serial:///dev/ttyUSB0"),
+            "serial:///dev/ttyUSB0?baud=9600"
+        );
+        assert_eq!(
+            parse("serial:///dev/ttyUSB0?baud=19200"),
+            "serial:///dev/ttyUSB0?baud=19200"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for s in [
+            "tcp://",
+            "tcp:///",
+            "serial:///dev/ttyUSB0?baud=fast",
+            "serial:///dev/ttyUSB0?baud=",
+            "serial:///dev/ttyUSB0?parity=even",
+            "usb:///dev/usb/lp0",
+        ] {
+            assert!(s.parse::<Device>().is_err(), "{s:?} should not parse");
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use escpos::{
+    driver::Driver,
+    errors::{PrinterError, Result},
+};
+use serialport::SerialPort;
+
+use super::{device::Device, emulator};
+
+/// A virtual printer that records everything sent to it.
+///
+/// Whenever a job has been sent completely, the commands recorded since the
+/// printer was last initialized are saved to a file and decoded into a PNG of
+/// what a real printer would have printed. Since each job starts by
+/// initializing the printer, this usually shows the most recent job.
+struct Capture {
+    path: PathBuf,
+    width: u32,
+    bytes: Vec<u8>,
+    saved: bool,
+    responses: VecDeque<u8>,
+}
+
+impl Capture {
+    /// Response to every `DLE EOT n` status request, meaning that everything
+    /// is fine.
+    const STATUS_OK: u8 = 0b0001_0010;
+
+    fn save(&mut self) -> anyhow::Result<()> {
+        if self.saved {
+            return Ok(());
+        }
+        self.saved = true;
+
+        let path = self.path.with_extension("bin");
+        fs::write(&path, &self.bytes)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to save captured commands")?;
+
+        let image = emulator::decode(&self.bytes, self.width)
+            .context("Failed to decode captured commands")?;
+        image
+            .save(&self.path)
+            .with_context(|| format!("At {}", self.path.display()))
+            .context("Failed to save captured image")?;
+
+        Ok(())
+    }
+}
+
+enum Connection {
+    File(File),
+    Tcp(TcpStream),
+    Serial(Box<dyn SerialPort>),
+    Virtual(Capture),
+}
+
+/// Like escpos' drivers, but the device can also be read from.
+///
+/// All connections are non-blocking (or use a short timeout) so that reading
+/// doesn't hang forever if the printer doesn't respond. Instead, reads and
+/// writes wait for the device up to a timeout.
+#[derive(Clone)]
+pub struct DeviceDriver {
+    device: Device,
+    connection: Rc<RefCell<Connection>>,
+}
+
+impl DeviceDriver {
+    /// How long to wait for a response from the printer.
+    const READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+    /// How long the printer may refuse to accept more data. Printing long
+    /// images can take a while, so this is fairly generous.
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+    /// The width is only used by virtual printers.
+    pub fn open(device: &Device, width: u32) -> anyhow::Result<Self> {
+        let connection = match device {
+            Device::File(path) => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(path)?;
+                Connection::File(file)
+            }
+
+            Device::Tcp(addr) => {
+                let mut last_err = None;
+                let mut stream = None;
+                for addr in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, Self::CONNECT_TIMEOUT) {
+                        Ok(it) => {
+                            stream = Some(it);
+                            break;
+                        }
+                        Err(err) => last_err = Some(err),
+                    }
+                }
+                let Some(stream) = stream else {
+                    match last_err {
+                        Some(err) => return Err(err.into()),
+                        None => bail!("{addr} resolved to no addresses"),
+                    }
+                };
+                stream.set_nodelay(true)?;
+                stream.set_nonblocking(true)?;
+                Connection::Tcp(stream)
+            }
+
+            Device::Serial { path, baud } => {
+                let port = serialport::new(path.to_string_lossy(), *baud)
+                    .timeout(Self::POLL_INTERVAL)
+                    .open()
+                    .context("Failed to open serial port")?;
+                Connection::Serial(port)
+            }
+
+            Device::Virtual(path) => Connection::Virtual(Capture {
+                path: path.clone(),
+                width,
+                bytes: vec![],
+                saved: true,
+                responses: VecDeque::new(),
+            }),
+        };
+
+        Ok(Self {
+            device: device.clone(),
+            connection: Rc::new(RefCell::new(connection)),
+        })
+    }
+
+    /// Save what a virtual printer has printed so far.
+    ///
+    /// Decoding the commands gets slower the more there are, so this is only
+    /// done once a job has been sent and not every time the driver is flushed.
+    pub fn save_capture(&self) -> anyhow::Result<()> {
+        match &mut *self.connection.borrow_mut() {
+            Connection::Virtual(capture) => capture.save(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Serial ports time out instead of blocking, which is treated the same.
+    fn is_blocking(err: &io::Error) -> bool {
+        matches!(
+            err.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+        )
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Serial(port) => port.read(buf),
+            Self::Virtual(capture) => {
+                if capture.responses.is_empty() {
+                    return Err(ErrorKind::WouldBlock.into());
+                }
+                let n = buf.len().min(capture.responses.len());
+                for (byte, response) in buf.iter_mut().zip(capture.responses.drain(..n)) {
+                    *byte = response;
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Serial(port) => port.write(buf),
+            Self::Virtual(capture) => {
+                // Status requests are always sent on their own.
+                if let [0x10, 0x04, _] = buf {
+                    capture.responses.push_back(Capture::STATUS_OK);
+                    return Ok(buf.len());
+                }
+                if buf.starts_with(&[0x1B, b'@']) {
+                    capture.bytes.clear();
+                }
+                capture.bytes.extend_from_slice(buf);
+                capture.saved = false;
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Tcp(stream) => stream.flush(),
+            Self::Serial(port) => port.flush(),
+            Self::Virtual(_) => Ok(()),
+        }
+    }
+}
+
+impl Driver for DeviceDriver {
+    fn name(&self) -> String {
+        format!("Device ({})", self.device)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<()> {
+        let mut connection = self.connection.borrow_mut();
+        let mut data = data;
+        let mut last_progress = Instant::now();
+        while !data.is_empty() {
+            match connection.write(data) {
+                Ok(0) => return Err(PrinterError::Io("device accepts no more data".to_string())),
+                Ok(n) => {
+                    data = &data[n..];
+                    last_progress = Instant::now();
+                }
+                Err(err) if Self::is_blocking(&err) => {
+                    if last_progress.elapsed() > Self::WRITE_TIMEOUT {
+                        return Err(PrinterError::Io("timed out writing to device".to_string()));
+                    }
+                    thread::sleep(Self::POLL_INTERVAL);
+                }
+                Err(err) => return Err(PrinterError::Io(err.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `Ok(0)` if nothing could be read before the timeout.
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut connection = self.connection.borrow_mut();
+        let start = Instant::now();
+        loop {
+            match connection.read(buf) {
+                Ok(0) if matches!(*connection, Connection::Tcp(_)) => {
+                    return Err(PrinterError::Io("connection closed by printer".to_string()));
+                }
+                Ok(n) if n > 0 => return Ok(n),
+                Ok(_) => {}
+                Err(err) if Self::is_blocking(&err) => {}
+                Err(err) => return Err(PrinterError::Io(err.to_string())),
+            }
+            if start.elapsed() > Self::READ_TIMEOUT {
+                return Ok(0);
+            }
+            thread::sleep(Self::POLL_INTERVAL);
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut connection = self.connection.borrow_mut();
+        let start = Instant::now();
+        loop {
+            match connection.flush() {
+                Ok(()) => return Ok(()),
+                Err(err) if Self::is_blocking(&err) => {
+                    if start.elapsed() > Self::WRITE_TIMEOUT {
+                        return Err(PrinterError::Io("timed out flushing device".to_string()));
+                    }
+                    thread::sleep(Self::POLL_INTERVAL);
+                }
+                Err(err) => return Err(PrinterError::Io(err.to_string())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use escpos::driver::Driver;
+
+    use super::{super::status::PrinterStatus, Device, DeviceDriver};
+
+    fn listen() -> (TcpListener, Device) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        (listener, Device::Tcp(addr.to_string()))
+    }
+
+    #[test]
+    fn tcp_write() {
+        let (listener, device) = listen();
+        let driver = DeviceDriver::open(&device, 384).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        // Large enough that the socket buffers fill up and writes would block.
+        let data = (0..4_000_000).map(|it| it as u8).collect::<Vec<_>>();
+        let reader = thread::spawn(move || {
+            let mut received = vec![];
+            peer.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        driver.write(&data).unwrap();
+        driver.flush().unwrap();
+        drop(driver);
+
+        assert!(reader.join().unwrap() == data);
+    }
+
+    #[test]
+    fn tcp_status() {
+        let (listener, device) = listen();
+        let driver = DeviceDriver::open(&device, 384).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let responder = thread::spawn(move || {
+            for n in 1..=4 {
+                let mut request = [0; 3];
+                peer.read_exact(&mut request).unwrap();
+                assert_eq!(request, [0x10, 0x04, n]);
+                // Paper near end, as reported by the paper sensor.
+                let response = if n == 4 { 0b0001_1110 } else { 0b0001_0010 };
+                peer.write_all(&[response]).unwrap();
+            }
+            peer
+        });
+
+        let status = PrinterStatus::request_all(&driver).unwrap().unwrap();
+        assert!(status.online);
+        assert!(status.paper_near_end);
+        assert!(status.can_print());
+
+        // Once the printer stops answering, its status is unknown.
+        let _peer = responder.join().unwrap();
+        assert!(PrinterStatus::request_all(&driver).unwrap().is_none());
+    }
+
+    #[test]
+    fn tcp_reconnect() {
+        let (listener, device) = listen();
+        let driver = DeviceDriver::open(&device, 384).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+
+        // The printer was switched off.
+        drop(peer);
+        assert!(PrinterStatus::request_all(&driver).is_err());
+
+        let driver = DeviceDriver::open(&device, 384).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        driver.write(b"hello").unwrap();
+        driver.flush().unwrap();
+        let mut buf = [0; 5];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn tcp_refused() {
+        let (listener, device) = listen();
+        drop(listener);
+        assert!(DeviceDriver::open(&device, 384).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::{Context, bail};
+use image::RgbaImage;
+
+use super::{BLACK, WHITE};
+
+/// Line spacing after `ESC @` or `ESC 2`, in dots.
+const DEFAULT_LINE_SPACING: u32 = 30;
+
+/// Size of a character in the printer's built-in font, in dots.
+const CHAR_WIDTH: u32 = 12;
+const CHAR_HEIGHT: u32 = 24;
+
+const LF: u8 = 0x0A;
+const CR: u8 = 0x0D;
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let Some(bytes) = self.bytes.get(self.pos..self.pos + n) else {
+            bail!("unexpected end of data at byte {}", self.pos);
+        };
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u32> {
+        let [l, h] = self.take(2)?.try_into().unwrap();
+        Ok(u16::from_le_bytes([l, h]).into())
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+/// Raster graphics stored in the print buffer by `GS ( L` / `GS 8 L`.
+struct Graphics {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+/// Pretends to be a printer, drawing the commands it receives onto paper.
+///
+/// Only the commands this program sends are understood. Since there is no
+/// font, text is drawn as one block per character.
+struct Emulator {
+    width: u32,
+    rows: Vec<Vec<bool>>,
+    /// Top of the current line.
+    y: u32,
+    /// Position of the next character in the current line.
+    x: u32,
+    /// Height of the tallest thing in the current line.
+    line_height: u32,
+    line_spacing: u32,
+    char_scale: (u32, u32),
+    graphics: Option<Graphics>,
+}
+
+impl Emulator {
+    fn new(width: u32) -> Self {
+        Self {
+            width,
+            rows: vec![],
+            y: 0,
+            x: 0,
+            line_height: 0,
+            line_spacing: DEFAULT_LINE_SPACING,
+            char_scale: (1, 1),
+            graphics: None,
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32) {
+        if x >= self.width {
+            return;
+        }
+        while self.rows.len() <= y as usize {
+            self.rows.push(vec![false; self.width as usize]);
+        }
+        self.rows[y as usize][x as usize] = true;
+    }
+
+    /// Draw rows of horizontal bytes, MSB first, at the left edge of the paper.
+    fn draw_raster(&mut self, width_bytes: u32, height: u32, data: &[u8]) {
+        for dy in 0..height {
+            for bx in 0..width_bytes {
+                let byte = data[(dy * width_bytes + bx) as usize];
+                for bit in 0..8 {
+                    if byte & (0b1000_0000 >> bit) != 0 {
+                        self.set(bx * 8 + bit, self.y + dy);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Mark where the paper was cut with a dashed line.
+    fn draw_cut(&mut self) {
+        for x in (0..self.width).filter(|x| x % 8 < 4) {
+            self.set(x, self.y);
+        }
+        self.y += 1;
+    }
+
+    fn print_line(&mut self) {
+        self.y += self.line_spacing.max(self.line_height);
+        self.x = 0;
+        self.line_height = 0;
+    }
+
+    /// Print the current line if it has any content and feed the paper.
+    fn feed(&mut self, dots: u32) {
+        if self.x > 0 {
+            self.y += self.line_height;
+        }
+        self.y += dots;
+        self.x = 0;
+        self.line_height = 0;
+    }
+
+    fn char(&mut self, c: u8) {
+        let (sx, sy) = self.char_scale;
+        let (width, height) = (CHAR_WIDTH * sx, CHAR_HEIGHT * sy);
+        if self.x + width > self.width {
+            self.print_line();
+        }
+
+        if c != b' ' {
+            // Leave a gap between characters and lines.
+            for dy in 2 * sy..height - 2 * sy {
+                for dx in sx..width - sx {
+                    self.set(self.x + dx, self.y + dy);
+                }
+            }
+        }
+
+        self.x += width;
+        self.line_height = self.line_height.max(height);
+    }
+
+    fn esc(&mut self, r: &mut Reader<'_>) -> anyhow::Result<()> {
+        match r.byte()? {
+            // ESC @: Initialize printer
+            b'@' => {
+                self.line_spacing = DEFAULT_LINE_SPACING;
+                self.char_scale = (1, 1);
+                self.graphics = None;
+            }
+
+            // ESC t, ESC a, ESC E, ESC -: Page code, alignment, bold, underline
+            b't' | b'a' | b'E' | b'-' => {
+                r.byte()?;
+            }
+
+            // ESC 2: Default line spacing
+            b'2' => self.line_spacing = DEFAULT_LINE_SPACING,
+
+            // ESC 3 n: Set line spacing
+            b'3' => self.line_spacing = r.byte()?.into(),
+
+            // ESC J n: Print and feed paper
+            b'J' => {
+                let n = r.byte()?;
+                self.feed(n.into());
+            }
+
+            // ESC d n: Print and feed n lines
+            b'd' => {
+                let n = r.byte()?;
+                self.feed(self.line_spacing * n as u32);
+            }
+
+            // ESC * m nL nH d1...dk: Bit image
+            b'*' => {
+                let m = r.byte()?;
+                let columns = r.u16()?;
+                let (bytes_per_column, x_scale) = match m {
+                    0 => (1, 2),
+                    1 => (1, 1),
+                    32 => (3, 2),
+                    33 => (3, 1),
+                    _ => bail!("unknown ESC * mode {m}"),
+                };
+                let data = r.take((columns * bytes_per_column) as usize)?;
+                for column in 0..columns {
+                    for i in 0..bytes_per_column {
+                        let byte = data[(column * bytes_per_column + i) as usize];
+                        for bit in 0..8 {
+                            if byte & (0b1000_0000 >> bit) != 0 {
+                                for sx in 0..x_scale {
+                                    let x = self.x + column * x_scale + sx;
+                                    self.set(x, self.y + i * 8 + bit);
+                                }
+                            }
+                        }
+                    }
+                }
+                // Unlike text, bit images don't make the line taller. The paper
+                // is only fed by the line spacing, so an image can be printed
+                // as lines of any height.
+                self.x += columns * x_scale;
+            }
+
+            other => bail!("unknown command ESC {other:#04x}"),
+        }
+        Ok(())
+    }
+
+    fn gs(&mut self, r: &mut Reader<'_>) -> anyhow::Result<()> {
+        match r.byte()? {
+            // GS ! n: Character size
+            b'!' => {
+                let n = r.byte()? as u32;
+                self.char_scale = ((n >> 4) + 1, (n & 0xF) + 1);
+            }
+
+            // GS v 0 m xL xH yL yH d1...dk: Raster image
+            b'v' => {
+                if r.byte()? != b'0' {
+                    bail!("unknown command GS v");
+                }
+                let _m = r.byte()?;
+                let width_bytes = r.u16()?;
+                let height = r.u16()?;
+                let data = r.take((width_bytes * height) as usize)?;
+                self.draw_raster(width_bytes, height, data);
+                self.y += height;
+            }
+
+            // GS ( L pL pH m fn ...: Graphics
+            b'(' => {
+                if r.byte()? != b'L' {
+                    bail!("unknown command GS (");
+                }
+                let len = r.u16()?;
+                let args = r.take(len as usize)?;
+                self.graphics_command(args)?;
+            }
+
+            // GS 8 L p1 p2 p3 p4 m fn ...: Graphics with a longer length
+            b'8' => {
+                if r.byte()? != b'L' {
+                    bail!("unknown command GS 8");
+                }
+                let len = r.u32()?;
+                let args = r.take(len as usize)?;
+                self.graphics_command(args)?;
+            }
+
+            // GS V m [n]: Cut paper, optionally feeding first
+            b'V' => {
+                match r.byte()? {
+                    0 | 1 | 48 | 49 => self.feed(0),
+                    65 | 66 | 97 | 98 | 103 | 104 => {
+                        let n = r.byte()?;
+                        self.feed(n.into());
+                    }
+                    m => bail!("unknown GS V mode {m}"),
+                }
+                self.draw_cut();
+            }
+
+            other => bail!("unknown command GS {other:#04x}"),
+        }
+        Ok(())
+    }
+
+    fn graphics_command(&mut self, args: &[u8]) -> anyhow::Result<()> {
+        let mut r = Reader {
+            bytes: args,
+            pos: 0,
+        };
+        let _m = r.byte()?;
+        match r.byte()? {
+            // fn 112: Store raster graphics data
+            112 => {
+                let [a, bx, by, c] = r.take(4)?.try_into().unwrap();
+                if (a, bx, by, c) != (48, 1, 1, 49) {
+                    bail!("unsupported graphics format a={a} bx={bx} by={by} c={c}");
+                }
+                let width = r.u16()?;
+                let height = r.u16()?;
+                let data = r.take((width.div_ceil(8) * height) as usize)?;
+                self.graphics = Some(Graphics {
+                    width,
+                    height,
+                    data: data.to_vec(),
+                });
+            }
+
+            // fn 50: Print stored graphics data
+            50 => {
+                let Some(graphics) = self.graphics.take() else {
+                    bail!("no graphics stored");
+                };
+                self.draw_raster(graphics.width.div_ceil(8), graphics.height, &graphics.data);
+                self.y += graphics.height;
+            }
+
+            other => bail!("unknown graphics function {other}"),
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        let mut r = Reader { bytes, pos: 0 };
+        while !r.is_empty() {
+            let start = r.pos;
+            let result = match r.byte()? {
+                LF => {
+                    self.print_line();
+                    Ok(())
+                }
+                CR => Ok(()),
+                ESC => self.esc(&mut r),
+                GS => self.gs(&mut r),
+                c if c >= 0x20 => {
+                    self.char(c);
+                    Ok(())
+                }
+                c => Err(anyhow::anyhow!("unknown control character {c:#04x}")),
+            };
+            result.with_context(|| format!("At byte {start}"))?;
+        }
+        Ok(())
+    }
+
+    fn into_image(self) -> RgbaImage {
+        let height = self.y.max(self.rows.len() as u32);
+        RgbaImage::from_fn(self.width, height, |x, y| {
+            let black = self.rows.get(y as usize).is_some_and(|row| row[x as usize]);
+            if black { BLACK } else { WHITE }
+        })
+    }
+}
+
+/// Render a stream of ESC/POS commands into an image of the printed paper.
+pub fn decode(bytes: &[u8], width: u32) -> anyhow::Result<RgbaImage> {
+    let mut emulator = Emulator::new(width);
+    emulator.run(bytes)?;
+    Ok(emulator.into_image())
+}
@@ -0,0 +1,338 @@
+use anyhow::bail;
+use clap::ValueEnum;
+use escpos::utils::{ESC, GS};
+use image::RgbaImage;
+use serde::Deserialize;
+
+use super::{Conversion, Converter, Printer};
+
+/// Commands printing a horizontal strip of an image.
+///
+/// Each chunk can be sent on its own, so an interrupted print can be resumed
+/// at any chunk boundary.
+pub struct Chunk {
+    /// Number of rows of the image printed by the chunk.
+    pub height: u32,
+    pub commands: Vec<u8>,
+}
+
+/// The command set used to send raster images to the printer.
+///
+/// Each mode splits the image into chunks that stay within the limits of its
+/// command. In-between chunks, the paper is not moved, meaning that chunks
+/// connect to each other seamlessly.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RasterMode {
+    /// The obsolete `GS v 0` command.
+    ///
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lv_0.html>
+    #[default]
+    GsV0,
+
+    /// The `GS ( L` / `GS 8 L` graphics commands.
+    ///
+    /// Each chunk is stored in the print buffer and then printed. `GS 8 L` is
+    /// only used if a chunk is too large for `GS ( L`.
+    ///
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lparen_cl_fn112.html>
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_8l_fn112.html>
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lparen_cl_fn50.html>
+    Graphics,
+
+    /// The `ESC *` bit image command in 24-dot double density mode, for very
+    /// old printers that support neither of the other commands.
+    ///
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/esc_asterisk.html>
+    EscStar,
+}
+
+impl RasterMode {
+    /// Maximum height of a `GS v 0` chunk.
+    ///
+    /// Looking at the [epson docs][0], most printers seem to support a max
+    /// height of 2303, though some go up to 4095. Because I don't want to waste
+    /// a bunch of paper trying various different heights, I'll go with 1023
+    /// because it's nice and round and slightly conservative.
+    ///
+    /// [0]: https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lv_0.html
+    const GS_V0_CHUNK_HEIGHT: u32 = 0b0000_0011_1111_1111;
+
+    /// Maximum width of a `GS v 0` image in bytes.
+    const GS_V0_MAX_WIDTH_BYTES: u32 = 4255;
+
+    /// Maximum height of a graphics chunk.
+    ///
+    /// The docs list maximum heights between 1200 and 2400 dots depending on
+    /// the printer model, so the same conservative value as for `GS v 0` is
+    /// used.
+    const GRAPHICS_CHUNK_HEIGHT: u32 = Self::GS_V0_CHUNK_HEIGHT;
+
+    /// Maximum width of a graphics chunk in pixels.
+    const GRAPHICS_MAX_WIDTH: u32 = 2400;
+
+    /// The parameters of `GS ( L` are limited to two bytes. They consist of
+    /// the image data and 10 bytes of other arguments.
+    const GRAPHICS_MAX_SHORT_LEN: usize = u16::MAX as usize - 10;
+
+    /// Height of a single `ESC *` line in 24-dot mode.
+    const ESC_STAR_CHUNK_HEIGHT: u32 = 24;
+
+    /// Maximum width of an `ESC *` image in pixels.
+    const ESC_STAR_MAX_WIDTH: u32 = 1023;
+
+    /// Width of the widest image the mode can print, in pixels.
+    pub fn max_width(self) -> u32 {
+        match self {
+            Self::GsV0 => Self::GS_V0_MAX_WIDTH_BYTES * 8,
+            Self::Graphics => Self::GRAPHICS_MAX_WIDTH,
+            Self::EscStar => Self::ESC_STAR_MAX_WIDTH,
+        }
+    }
+
+    /// Encode an image into a series of chunks that print it from top to
+    /// bottom.
+    ///
+    /// The image's width must be a multiple of 8 and at most
+    /// [`Self::max_width`].
+    pub fn encode(self, image: &RgbaImage, conversion: &Conversion) -> anyhow::Result<Vec<Chunk>> {
+        let width = image.width();
+        if !width.is_multiple_of(8) {
+            bail!("image width {width} is not a multiple of 8");
+        }
+        if width > self.max_width() {
+            bail!(
+                "image width {width} exceeds the maximum of {}",
+                self.max_width()
+            );
+        }
+
+        let converter = conversion.converter();
+        let chunks = match self {
+            Self::GsV0 => Self::encode_gs_v0(image, &converter),
+            Self::Graphics => Self::encode_graphics(image, &converter),
+            Self::EscStar => Self::encode_esc_star(image, &converter),
+        };
+        Ok(chunks)
+    }
+
+    /// Split the image into chunks of at most `chunk_height` rows.
+    fn chunks(image: &RgbaImage, chunk_height: u32) -> impl Iterator<Item = (u32, u32)> {
+        (0..image.height())
+            .step_by(chunk_height as usize)
+            .map(move |y_offset| (y_offset, chunk_height.min(image.height() - y_offset)))
+    }
+
+    fn encode_gs_v0(image: &RgbaImage, converter: &Converter) -> Vec<Chunk> {
+        // The command takes the width in bytes (groups of 8 pixels) and the
+        // height in pixels. Both are then split into two bytes and sent.
+        let width = image.width() / 8;
+
+        Self::chunks(image, Self::GS_V0_CHUNK_HEIGHT)
+            .map(|(y_offset, height)| {
+                let m = 0; // Normal resolution
+                let [_, _, x_h, x_l] = width.to_be_bytes();
+                let [_, _, y_h, y_l] = height.to_be_bytes();
+                let mut commands = vec![GS, b'v', b'0', m, x_l, x_h, y_l, y_h];
+                commands.extend(Printer::get_horizontal_bytes(
+                    image, converter, y_offset, height,
+                ));
+                Chunk { height, commands }
+            })
+            .collect()
+    }
+
+    fn encode_graphics(image: &RgbaImage, converter: &Converter) -> Vec<Chunk> {
+        let width = image.width();
+
+        let mut chunks = vec![];
+        for (y_offset, height) in Self::chunks(image, Self::GRAPHICS_CHUNK_HEIGHT) {
+            let data = Printer::get_horizontal_bytes(image, converter, y_offset, height);
+
+            let [_, _, x_h, x_l] = width.to_be_bytes();
+            let [_, _, y_h, y_l] = height.to_be_bytes();
+            let args = [
+                48,  // m
+                112, // fn: Store raster graphics data in the print buffer
+                48,  // a: Monochrome
+                1,   // bx: Normal width
+                1,   // by: Normal height
+                49,  // c: First color
+                x_l, x_h, y_l, y_h,
+            ];
+
+            // The parameter length counts everything after itself.
+            let len = args.len() + data.len();
+            let mut commands = if data.len() <= Self::GRAPHICS_MAX_SHORT_LEN {
+                let [_, _, p_h, p_l] = (len as u32).to_be_bytes();
+                vec![GS, b'(', b'L', p_l, p_h]
+            } else {
+                let [p4, p3, p2, p1] = (len as u32).to_be_bytes();
+                vec![GS, b'8', b'L', p1, p2, p3, p4]
+            };
+            commands.extend(args);
+            commands.extend(data);
+
+            // fn 50: Print the graphics data in the print buffer
+            commands.extend([GS, b'(', b'L', 2, 0, 48, 50]);
+
+            chunks.push(Chunk { height, commands });
+        }
+        chunks
+    }
+
+    fn encode_esc_star(image: &RgbaImage, converter: &Converter) -> Vec<Chunk> {
+        let width = image.width();
+
+        let mut chunks = vec![];
+        for (y_offset, height) in Self::chunks(image, Self::ESC_STAR_CHUNK_HEIGHT) {
+            // Advance the paper by the chunk's height, which is less than a
+            // full line for the last chunk. This is repeated for every chunk
+            // so that each chunk can be sent on its own.
+            let mut commands = vec![ESC, b'3', height as u8];
+
+            let m = 33; // 24-dot double density
+            let [_, _, n_h, n_l] = width.to_be_bytes();
+            commands.extend([ESC, b'*', m, n_l, n_h]);
+
+            // Each column consists of three vertical bytes, MSB at the top.
+            // Rows below the end of the image stay white.
+            for x in 0..width {
+                for y in (y_offset..y_offset + Self::ESC_STAR_CHUNK_HEIGHT).step_by(8) {
+                    commands.push(Printer::get_vertical_byte_starting_at(
+                        image,
+                        converter,
+                        x,
+                        y,
+                        y_offset + height,
+                    ));
+                }
+            }
+
+            commands.push(b'\n');
+
+            // Restore the default line spacing.
+            commands.extend([ESC, b'2']);
+
+            chunks.push(Chunk { height, commands });
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{
+        super::{Weighting, emulator},
+        Chunk, Conversion, Printer, RasterMode,
+    };
+
+    const MODES: [RasterMode; 3] = [RasterMode::GsV0, RasterMode::Graphics, RasterMode::EscStar];
+
+    /// Random colors, including partially transparent ones.
+    fn noise(width: u32, height: u32) -> RgbaImage {
+        let mut state = 0x2545_f491_u32;
+        RgbaImage::from_fn(width, height, |_, _| {
+            // https://en.wikipedia.org/wiki/Xorshift
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            Rgba(state.to_le_bytes())
+        })
+    }
+
+    fn commands(chunks: &[Chunk]) -> Vec<u8> {
+        chunks.iter().flat_map(|it| it.commands.clone()).collect()
+    }
+
+    fn assert_printed(printed: &RgbaImage, image: &RgbaImage, conversion: &Conversion) {
+        let expected = Printer::threshold(image, conversion);
+        assert_eq!(printed.dimensions(), expected.dimensions());
+        assert!(*printed == expected, "printed image differs");
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        let conversion = Conversion::default();
+        for mode in MODES {
+            for height in [1, 23, 24, 25, 1022, 1023, 1024, 2047, 2100] {
+                let image = noise(384, height);
+                let chunks = mode.encode(&image, &conversion).unwrap();
+                assert_eq!(chunks.iter().map(|it| it.height).sum::<u32>(), height);
+                let printed = emulator::decode(&commands(&chunks), 384).unwrap();
+                assert_printed(&printed, &image, &conversion);
+            }
+        }
+    }
+
+    #[test]
+    fn max_width() {
+        let conversion = Conversion::default();
+        for mode in MODES {
+            let width = mode.max_width() / 8 * 8;
+            assert!(mode.encode(&noise(width, 1), &conversion).is_ok());
+            assert!(mode.encode(&noise(width + 8, 1), &conversion).is_err());
+        }
+    }
+
+    #[test]
+    fn long_graphics_command() {
+        let conversion = Conversion::default();
+        let image = noise(576, 1100);
+        let chunks = RasterMode::Graphics.encode(&image, &conversion).unwrap();
+        assert_eq!(chunks[0].commands[..3], *b"\x1d8L");
+        assert_eq!(chunks[1].commands[..3], *b"\x1d(L");
+        let printed = emulator::decode(&commands(&chunks), 576).unwrap();
+        assert_printed(&printed, &image, &conversion);
+    }
+
+    #[test]
+    fn conversions() {
+        let conversions = [
+            Conversion {
+                threshold: 0.3,
+                ..Conversion::default()
+            },
+            Conversion {
+                gamma: 2.2,
+                weighting: Weighting::Rec709,
+                ..Conversion::default()
+            },
+            Conversion {
+                alpha: true,
+                ..Conversion::default()
+            },
+        ];
+
+        let image = noise(384, 50);
+        for conversion in conversions {
+            for mode in MODES {
+                let chunks = mode.encode(&image, &conversion).unwrap();
+                let printed = emulator::decode(&commands(&chunks), 384).unwrap();
+                assert_printed(&printed, &image, &conversion);
+            }
+        }
+    }
+
+    #[test]
+    fn resume() {
+        let conversion = Conversion::default();
+        let image = noise(384, 2100);
+        for mode in MODES {
+            let chunks = mode.encode(&image, &conversion).unwrap();
+            for sent in [1, chunks.len() / 2, chunks.len() - 1] {
+                // The first chunks were printed before the printer failed.
+                let sent = &chunks[..sent];
+                let printed_rows = sent.iter().map(|it| it.height).sum();
+
+                let remaining = Printer::remaining_rows(&image, printed_rows);
+                let mut bytes = commands(sent);
+                bytes.extend(commands(&mode.encode(&remaining, &conversion).unwrap()));
+                let printed = emulator::decode(&bytes, 384).unwrap();
+                assert_printed(&printed, &image, &conversion);
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::bail;
+use escpos::driver::Driver;
+use serde::Serialize;
+
+use super::driver::DeviceDriver;
+
+/// The printer's state as reported by `DLE EOT`.
+///
+/// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/dle_eot.html>
+#[derive(Debug, Clone, Serialize)]
+pub struct PrinterStatus {
+    pub online: bool,
+    pub cover_open: bool,
+    /// Paper is being fed using the paper feed button.
+    pub feeding: bool,
+    pub paper_end: bool,
+    pub paper_near_end: bool,
+    /// An error occurred, see the more specific error fields.
+    pub error: bool,
+    pub cutter_error: bool,
+    pub unrecoverable_error: bool,
+    /// An error that goes away by itself, e.g. if the head overheated.
+    pub auto_recoverable_error: bool,
+}
+
+impl PrinterStatus {
+    const DLE: u8 = 0x10;
+    const EOT: u8 = 0x04;
+
+    /// Request a single status byte.
+    ///
+    /// Every status byte has bits 1 and 4 set and bits 0 and 7 unset, which is
+    /// used to tell status bytes apart from garbage. Returns `None` if the
+    /// printer didn't respond.
+    fn request(driver: &DeviceDriver, n: u8) -> anyhow::Result<Option<u8>> {
+        driver.write(&[Self::DLE, Self::EOT, n])?;
+        driver.flush()?;
+
+        let mut buf = [0];
+        if driver.read(&mut buf)? == 0 {
+            return Ok(None);
+        }
+
+        let [byte] = buf;
+        if byte & 0b1001_0011 != 0b0001_0010 {
+            bail!("invalid status byte {byte:#010b}");
+        }
+
+        Ok(Some(byte))
+    }
+
+    /// Ask the printer for its status.
+    ///
+    /// Returns `None` if the printer doesn't support status requests.
+    pub fn request_all(driver: &DeviceDriver) -> anyhow::Result<Option<Self>> {
+        let Some(printer) = Self::request(driver, 1)? else {
+            return Ok(None);
+        };
+        let Some(offline) = Self::request(driver, 2)? else {
+            return Ok(None);
+        };
+        let Some(error) = Self::request(driver, 3)? else {
+            return Ok(None);
+        };
+        let Some(paper) = Self::request(driver, 4)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            online: printer & 0b0000_1000 == 0,
+            cover_open: offline & 0b0000_0100 != 0,
+            feeding: offline & 0b0000_1000 != 0,
+            paper_end: offline & 0b0010_0000 != 0 || paper & 0b0110_0000 != 0,
+            paper_near_end: paper & 0b0000_1100 != 0,
+            error: offline & 0b0100_0000 != 0,
+            cutter_error: error & 0b0000_1000 != 0,
+            unrecoverable_error: error & 0b0010_0000 != 0,
+            auto_recoverable_error: error & 0b0100_0000 != 0,
+        }))
+    }
+
+    /// Human-readable reasons why the printer can't print right now.
+    pub fn problems(&self) -> Vec<&'static str> {
+        let mut problems = vec![];
+        if self.cover_open {
+            problems.push("cover is open");
+        }
+        if self.paper_end {
+            problems.push("out of paper");
+        }
+        if self.error {
+            problems.push("printer error");
+        }
+        if !self.online && problems.is_empty() {
+            problems.push("printer is offline");
+        }
+        problems
+    }
+
+    pub fn can_print(&self) -> bool {
+        self.problems().is_empty()
+    }
+}
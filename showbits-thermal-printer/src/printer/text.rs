@@ -0,0 +1,116 @@
+use escpos::utils::{ESC, GS};
+use serde::{Deserialize, Serialize};
+
+/// The upper half of [code page 437][0], starting at `0x80`.
+///
+/// The lower half is mostly ASCII. Its non-ASCII glyphs are not included
+/// because the printer interprets those bytes as control characters.
+///
+/// [0]: https://en.wikipedia.org/wiki/Code_page_437
+#[rustfmt::skip]
+const PC437_UPPER: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+fn encode_char(c: char) -> Option<u8> {
+    if c == '\n' || c == ' ' || c.is_ascii_graphic() {
+        return Some(c as u8);
+    }
+    let index = PC437_UPPER.iter().position(|it| *it == c)?;
+    Some(0x80 + index as u8)
+}
+
+/// Encode text as PC437.
+///
+/// Characters that can't be represented are replaced by `fallback`, or by `?`
+/// if the fallback can't be represented either.
+pub fn encode_pc437(text: &str, fallback: char) -> Vec<u8> {
+    let fallback = encode_char(fallback).unwrap_or(b'?');
+    text.chars()
+        .filter(|it| *it != '\r')
+        .map(|it| if it == '\t' { ' ' } else { it })
+        .map(|it| encode_char(it).unwrap_or(fallback))
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Plain text printed with the printer's built-in font instead of as an image.
+pub struct Text {
+    pub text: String,
+    pub bold: bool,
+    pub underline: bool,
+    pub double_width: bool,
+    pub double_height: bool,
+    pub align: Align,
+    /// Replacement for characters that aren't available in PC437.
+    pub fallback: char,
+    pub feed: bool,
+}
+
+impl Text {
+    /// How far the paper is moved after the text if `feed` is set, in dots.
+    ///
+    /// Roughly the same as the `feed` of the typst documents.
+    const FEED: u8 = 96;
+
+    /// The ESC/POS commands that print the text, assuming the printer has just
+    /// been initialized.
+    ///
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/esc_la.html>
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/esc_ce.html>
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/esc_minus.html>
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_exclamation.html>
+    /// <https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/esc_cj.html>
+    pub fn encode(&self) -> Vec<u8> {
+        let align = match self.align {
+            Align::Left => 0,
+            Align::Center => 1,
+            Align::Right => 2,
+        };
+        let size = match (self.double_width, self.double_height) {
+            (false, false) => 0x00,
+            (false, true) => 0x01,
+            (true, false) => 0x10,
+            (true, true) => 0x11,
+        };
+
+        let mut bytes = vec![];
+        bytes.extend([ESC, b'a', align]);
+        bytes.extend([ESC, b'E', self.bold as u8]);
+        bytes.extend([ESC, b'-', self.underline as u8]);
+        bytes.extend([GS, b'!', size]);
+
+        // Text is only printed once its line is terminated.
+        let mut text = encode_pc437(&self.text, self.fallback);
+        if !text.ends_with(b"\n") {
+            text.push(b'\n');
+        }
+        bytes.extend(text);
+
+        bytes.extend([ESC, b'a', 0]);
+        bytes.extend([ESC, b'E', 0]);
+        bytes.extend([ESC, b'-', 0]);
+        bytes.extend([GS, b'!', 0]);
+
+        if self.feed {
+            bytes.extend([ESC, b'J', Self::FEED]);
+        }
+
+        bytes
+    }
+}
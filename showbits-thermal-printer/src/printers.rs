@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    drawer::Command,
+    persistent_printer::StatusReport,
+    printer::{Conversion, Device, PrinterConfig},
+    queue::Queue,
+};
+
+/// Everything the server needs to talk to one printer's drawer.
+#[derive(Clone)]
+pub struct PrinterHandle {
+    pub name: String,
+    pub tx: mpsc::Sender<Command>,
+    pub queue: Queue,
+    pub paused: Arc<AtomicBool>,
+    pub status: Arc<Mutex<Option<StatusReport>>>,
+    pub device: Option<Device>,
+    /// Width of the printable area in pixels.
+    pub width: u32,
+    /// How the printer converts images to black and white, for previews.
+    pub conversion: Conversion,
+    active: Arc<AtomicUsize>,
+}
+
+impl PrinterHandle {
+    pub fn new(
+        name: String,
+        tx: mpsc::Sender<Command>,
+        queue: Queue,
+        config: &PrinterConfig,
+    ) -> Self {
+        Self {
+            name,
+            tx,
+            queue,
+            paused: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(Mutex::new(None)),
+            device: config.device.clone(),
+            width: config.width,
+            conversion: config.conversion,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether the printer would print a job right away instead of queuing it,
+    /// judging by the most recent status check.
+    pub fn is_online(&self) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        match &*self.status.lock().unwrap() {
+            // Not checked yet, so nothing is known to be wrong.
+            None => true,
+            Some(report) => {
+                report.error.is_none() && report.status.as_ref().is_none_or(|it| it.can_print())
+            }
+        }
+    }
+
+    /// Number of jobs routed to this printer that haven't been printed or
+    /// queued yet.
+    pub fn active_jobs(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Count a job as active until the returned guard is dropped.
+    pub fn start_job(&self) -> ActiveJob {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ActiveJob(self.active.clone())
+    }
+}
+
+/// Keeps a job counted in [`PrinterHandle::active_jobs`] while it exists.
+pub struct ActiveJob(Arc<AtomicUsize>);
+
+impl Drop for ActiveJob {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// All configured printers by name, and which one jobs go to by default.
+///
+/// Instead of a specific printer, jobs can be sent to the [`Self::POOL`],
+/// which picks whichever printer is best suited to print them right now.
+#[derive(Clone)]
+pub struct Printers {
+    printers: Arc<BTreeMap<String, PrinterHandle>>,
+    default: String,
+}
+
+impl Printers {
+    /// Name that routes jobs to the pool of all printers.
+    pub const POOL: &str = "pool";
+
+    /// The default must either be one of the printers or [`Self::POOL`].
+    pub fn new(printers: Vec<PrinterHandle>, default: String) -> Self {
+        let printers = printers
+            .into_iter()
+            .map(|it| (it.name.clone(), it))
+            .collect::<BTreeMap<_, _>>();
+
+        assert!(default == Self::POOL || printers.contains_key(&default));
+
+        Self {
+            printers: Arc::new(printers),
+            default,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PrinterHandle> {
+        self.printers.values()
+    }
+
+    /// Fall back to the default printer if no name is given.
+    pub fn resolve<'a>(&'a self, name: Option<&'a str>) -> &'a str {
+        name.unwrap_or(&self.default)
+    }
+
+    /// Look up a printer by name. The pool is not a printer.
+    pub fn get(&self, name: &str) -> Option<&PrinterHandle> {
+        self.printers.get(name)
+    }
+
+    /// Like [`Self::get`], but picks a printer if the name refers to the pool.
+    pub fn route(&self, name: &str) -> Option<&PrinterHandle> {
+        match name {
+            Self::POOL => self.pick_from_pool(),
+            name => self.get(name),
+        }
+    }
+
+    /// Prefer printers that are online, then printers with fewer active jobs.
+    /// If all printers are offline, the job is queued on the least busy one.
+    fn pick_from_pool(&self) -> Option<&PrinterHandle> {
+        self.printers
+            .values()
+            .min_by_key(|it| (!it.is_online(), it.active_jobs()))
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, bail};
+use image::RgbaImage;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::{entry_id, jobs::JobId, printer::Cut};
+
+/// Metadata stored in a JSON sidecar file next to each queued image.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EntryMeta {
+    pub job: Option<JobId>,
+    pub document: String,
+    pub submitter: Option<String>,
+    /// The data the document was rendered with.
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// Overrides how the printer cuts the paper after this entry.
+    #[serde(default)]
+    pub cut: Option<Cut>,
+    pub queued: Timestamp,
+    /// Entries are printed in ascending order.
+    pub order: i64,
+    /// Failed attempts caused by the image itself rather than the printer.
+    pub attempts: u32,
+    /// Rows at the top of the image that were already printed before an
+    /// attempt failed. Only the remaining rows are printed.
+    #[serde(default)]
+    pub printed_rows: u32,
+    /// The printer error that caused the image to be queued instead of
+    /// printed. Unset if it was queued for another reason, e.g. because
+    /// printing was paused.
+    pub error: Option<String>,
+    /// The error of the most recent failed attempt at printing the image from
+    /// the queue.
+    pub last_error: Option<String>,
+}
+
+impl EntryMeta {
+    /// Metadata for images queued before sidecar files existed.
+    ///
+    /// Those images were named after the time they were queued at.
+    fn legacy(id: &str) -> Self {
+        let queued = id.parse().unwrap_or(Timestamp::UNIX_EPOCH);
+        Self {
+            job: None,
+            document: "unknown".to_string(),
+            submitter: None,
+            params: serde_json::Value::Null,
+            cut: None,
+            queued,
+            order: queued.as_millisecond(),
+            attempts: 0,
+            printed_rows: 0,
+            error: None,
+            last_error: None,
+        }
+    }
+}
+
+pub struct Entry {
+    pub id: String,
+    pub meta: EntryMeta,
+}
+
+/// A directory of images waiting to be printed.
+///
+/// Each entry consists of a `{id}.png` image and a `{id}.json` sidecar file
+/// containing its [`EntryMeta`]. Entries that failed too often or whose sidecar
+/// file is corrupt are moved into the dead letter subdirectory, where they are
+/// no longer retried.
+#[derive(Clone)]
+pub struct Queue {
+    dir: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Queue {
+    const DEAD_DIR: &str = "dead";
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn image_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.png"))
+    }
+
+    fn meta_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    fn write_meta(&self, id: &str, meta: &EntryMeta) -> anyhow::Result<()> {
+        let path = self.meta_path(id);
+        let json = serde_json::to_vec_pretty(meta).expect("meta should serialize to json");
+        fs::write(&path, json)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to write queue metadata")?;
+        Ok(())
+    }
+
+    /// Only images without a sidecar file get legacy metadata. A sidecar file
+    /// that can't be read is an error instead, since guessing its contents
+    /// would e.g. reprint rows that were already printed.
+    fn read_meta(&self, id: &str) -> anyhow::Result<EntryMeta> {
+        let path = self.meta_path(id);
+        let bytes = match fs::read(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(EntryMeta::legacy(id)),
+            result => result
+                .with_context(|| format!("At {}", path.display()))
+                .context("Failed to read queue metadata")?,
+        };
+        let meta = serde_json::from_slice(&bytes)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to parse queue metadata")?;
+        Ok(meta)
+    }
+
+    pub fn push(&self, image: &RgbaImage, meta: &EntryMeta) -> anyhow::Result<String> {
+        let _guard = self.lock.lock().unwrap();
+
+        let id = meta.queued.to_string();
+        let path = self.image_path(&id);
+        println!("Enqueuing image {}", path.display());
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("At {}", self.dir.display()))
+            .context("Failed to create queue directory")?;
+
+        image
+            .save(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to save image to queue")?;
+
+        self.write_meta(&id, meta)?;
+
+        Ok(id)
+    }
+
+    /// All entries in the order they should be printed in.
+    pub fn entries(&self) -> anyhow::Result<Vec<Entry>> {
+        let _guard = self.lock.lock().unwrap();
+        self.entries_locked()
+    }
+
+    /// Like [`Self::entries`], but the lock must already be held.
+    fn entries_locked(&self) -> anyhow::Result<Vec<Entry>> {
+        let mut entries = vec![];
+
+        match self.dir.read_dir() {
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+
+            Err(err) => Err(err)
+                .with_context(|| format!("At {}", self.dir.display()))
+                .context("Failed to open queue dir")?,
+
+            Ok(dir) => {
+                for entry in dir {
+                    let entry = entry?;
+                    if !entry.file_type()?.is_file() {
+                        continue;
+                    }
+                    let path = entry.path();
+                    if path.extension().is_none_or(|it| it != "png") {
+                        continue;
+                    }
+                    let Some(id) = path.file_stem().and_then(|it| it.to_str()) else {
+                        continue;
+                    };
+                    let meta = match self.read_meta(id) {
+                        Ok(meta) => meta,
+                        Err(err) => {
+                            println!("{err:#}");
+                            if let Err(err) = self.move_to_dead_dir(id, None) {
+                                println!("{err:#}");
+                            }
+                            continue;
+                        }
+                    };
+                    entries.push(Entry {
+                        id: id.to_string(),
+                        meta,
+                    });
+                }
+            }
+        }
+
+        entries.sort_unstable_by(|a, b| (a.meta.order, &a.id).cmp(&(b.meta.order, &b.id)));
+
+        Ok(entries)
+    }
+
+    /// Whether there are no entries, without reading their metadata.
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+
+        let dir = match self.dir.read_dir() {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(true),
+            result => result
+                .with_context(|| format!("At {}", self.dir.display()))
+                .context("Failed to open queue dir")?,
+        };
+
+        for entry in dir {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_file() && path.extension().is_some_and(|it| it == "png") {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        entry_id::check(id).is_ok() && self.image_path(id).is_file()
+    }
+
+    pub fn load_image(&self, id: &str) -> anyhow::Result<RgbaImage> {
+        entry_id::check(id)?;
+        let _guard = self.lock.lock().unwrap();
+        let path = self.image_path(id);
+        let image = image::open(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to load queued image")?
+            .into_rgba8();
+        Ok(image)
+    }
+
+    /// The queued PNG file exactly as it will be printed.
+    pub fn load_image_bytes(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        entry_id::check(id)?;
+        let _guard = self.lock.lock().unwrap();
+        let path = self.image_path(id);
+        let bytes = fs::read(&path)
+            .with_context(|| format!("At {}", path.display()))
+            .context("Failed to read queued image")?;
+        Ok(bytes)
+    }
+
+    /// Replace an entry's metadata.
+    ///
+    /// Entries that have been removed in the meantime stay removed.
+    pub fn update_meta(&self, id: &str, meta: &EntryMeta) -> anyhow::Result<()> {
+        entry_id::check(id)?;
+        let _guard = self.lock.lock().unwrap();
+        if !self.image_path(id).is_file() {
+            return Ok(());
+        }
+        self.write_meta(id, meta)
+    }
+
+    /// Change an entry's order so it is printed before all other entries.
+    pub fn move_to_front(&self, id: &str) -> anyhow::Result<()> {
+        entry_id::check(id)?;
+        let _guard = self.lock.lock().unwrap();
+        let entries = self.entries_locked()?;
+
+        let Some(entry) = entries.iter().find(|it| it.id == id) else {
+            bail!("queue entry {id:?} not found");
+        };
+
+        let mut meta = entry.meta.clone();
+        meta.order = entries.iter().map(|it| it.meta.order).min().unwrap_or(0) - 1;
+        self.write_meta(id, &meta)
+    }
+
+    /// Remove an entry, returning whether it still existed.
+    ///
+    /// Entries can be removed via the API while they are being printed, so an
+    /// entry that has already been removed is not an error.
+    pub fn remove(&self, id: &str) -> anyhow::Result<bool> {
+        entry_id::check(id)?;
+        let _guard = self.lock.lock().unwrap();
+
+        let path = self.image_path(id);
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            result => result
+                .with_context(|| format!("At {}", path.display()))
+                .context("Failed to remove queued image")?,
+        }
+
+        let path = self.meta_path(id);
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            result => result
+                .with_context(|| format!("At {}", path.display()))
+                .context("Failed to remove queue metadata")?,
+        }
+
+        Ok(true)
+    }
+
+    /// Remove all entries, returning the removed entries.
+    ///
+    /// Entries in the dead letter directory are kept.
+    pub fn clear(&self) -> anyhow::Result<Vec<Entry>> {
+        let mut removed = vec![];
+        for entry in self.entries()? {
+            if self.remove(&entry.id)? {
+                removed.push(entry);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Move an entry into the dead letter directory.
+    ///
+    /// Entries that have been removed in the meantime stay removed.
+    pub fn bury(&self, id: &str, meta: &EntryMeta) -> anyhow::Result<()> {
+        entry_id::check(id)?;
+        let _guard = self.lock.lock().unwrap();
+        self.move_to_dead_dir(id, Some(meta))
+    }
+
+    /// Move an entry into the dead letter directory, replacing its sidecar file
+    /// with `meta` if given. The lock must already be held.
+    fn move_to_dead_dir(&self, id: &str, meta: Option<&EntryMeta>) -> anyhow::Result<()> {
+        if !self.image_path(id).is_file() {
+            return Ok(());
+        }
+
+        let dead_dir = self.dir.join(Self::DEAD_DIR);
+        println!("Moving image {id} to {}", dead_dir.display());
+
+        fs::create_dir_all(&dead_dir)
+            .with_context(|| format!("At {}", dead_dir.display()))
+            .context("Failed to create dead letter directory")?;
+
+        let from = self.image_path(id);
+        let to = dead_dir.join(format!("{id}.png"));
+        fs::rename(&from, &to)
+            .with_context(|| format!("At {}", from.display()))
+            .context("Failed to move queued image")?;
+
+        if let Some(meta) = meta {
+            self.write_meta(id, meta)?;
+        }
+        let from = self.meta_path(id);
+        if !from.is_file() {
+            return Ok(());
+        }
+        let to = dead_dir.join(format!("{id}.json"));
+        fs::rename(&from, &to)
+            .with_context(|| format!("At {}", from.display()))
+            .context("Failed to move queue metadata")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads rendering documents for all printers.
+///
+/// Rendering happens independently of printing, so that a document that takes
+/// long to render doesn't hold up documents that are ready to be printed, and
+/// the other way around.
+#[derive(Clone)]
+pub struct RenderPool {
+    tx: mpsc::Sender<Task>,
+}
+
+impl RenderPool {
+    pub fn new(threads: usize) -> anyhow::Result<Self> {
+        let (tx, rx) = mpsc::channel::<Task>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..threads.max(1) {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("render {i}"))
+                .spawn(move || {
+                    loop {
+                        // The lock must be released before the task is run.
+                        let task = rx.lock().unwrap().recv();
+                        let Ok(task) = task else {
+                            break;
+                        };
+                        task();
+                    }
+                })?;
+        }
+
+        Ok(Self { tx })
+    }
+
+    pub fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+        let _ = self.tx.send(Box::new(task));
+    }
+}
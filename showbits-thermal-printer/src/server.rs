@@ -1,41 +1,196 @@
+mod archive;
+mod images;
+mod jobs;
+pub mod options;
+mod printer;
+mod queue;
 pub mod somehow;
 mod r#static;
 pub mod statuscode;
 
-use std::path::PathBuf;
+use std::{io::Cursor, net::SocketAddr, path::PathBuf};
 
+use anyhow::{Context, anyhow};
 use axum::{
-    Router,
+    Json, Router,
     extract::DefaultBodyLimit,
-    routing::{get, post},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
 };
+use image::{ImageFormat, RgbaImage};
+use serde::Serialize;
 use showbits_typst::Typst;
-use tokio::{
-    net::TcpListener,
-    sync::{mpsc, oneshot},
+use tokio::{net::TcpListener, sync::oneshot, task};
+
+use crate::{
+    archive::Archive,
+    documents,
+    drawer::Command,
+    jobs::{JobId, JobState, Jobs},
+    printer::{Conversion, Printer, Text},
+    printers::Printers,
 };
 
-use crate::{documents, drawer::Command};
+use self::{options::JobOptions, somehow::JobFailed, statuscode::status_code_with_info};
 
 #[derive(Clone)]
 pub struct Server {
-    tx: mpsc::Sender<Command>,
+    pub printers: Printers,
+    pub jobs: Jobs,
+    pub archive: Option<Archive>,
     pub originals: Option<PathBuf>,
+    pub packages: Option<PathBuf>,
+    pub network: bool,
 }
 
 impl Server {
-    pub async fn print_typst(&self, typst: Typst) -> somehow::Result<()> {
+    /// Submit a job to the printer chosen by the options and wait until it has
+    /// been printed or queued. Responds with the final state of the job, even
+    /// if the job failed.
+    async fn submit(
+        &self,
+        options: JobOptions,
+        document: &str,
+        params: &impl Serialize,
+        command: impl FnOnce(JobId, oneshot::Sender<anyhow::Result<()>>) -> Command,
+    ) -> somehow::Result<Response> {
+        let printer = &options.printer;
+        let params = serde_json::to_value(params)?;
+        let id = self.jobs.submit(
+            document,
+            &printer.name,
+            params,
+            options.submitter,
+            options.cut,
+        );
         let (tx, rx) = oneshot::channel();
-        let _ = self.tx.send(Command::Typst(typst, tx)).await;
-        rx.await?.map_err(somehow::Error)
+        let sent = printer.tx.send(command(id, tx)).await.is_ok();
+        let result = match rx.await {
+            Ok(result) => result,
+            // The printer marks failed jobs itself, but not if it isn't running.
+            Err(_) => {
+                let error = match sent {
+                    true => "printer stopped before finishing the job",
+                    false => "printer is not running",
+                };
+                let state = JobState::Failed {
+                    error: error.to_string(),
+                };
+                self.jobs.set_state(id, state);
+                Err(anyhow!(error))
+            }
+        };
+
+        if let Err(err) = result {
+            let Some(job) = self.jobs.get(id) else {
+                return Err(somehow::Error(err));
+            };
+            return Err(somehow::Error(err.context(JobFailed(Box::new(job)))));
+        }
+        Ok(Json(self.jobs.get(id)).into_response())
+    }
+
+    /// Submit a document for printing, see [`Self::submit`]. The params are
+    /// recorded with the job and should be the data the document is rendered
+    /// with.
+    ///
+    /// In preview mode, the document is rendered and returned as a PNG instead.
+    pub async fn print_typst(
+        &self,
+        options: JobOptions,
+        document: &str,
+        params: &impl Serialize,
+        typst: Typst,
+    ) -> somehow::Result<Response> {
+        let typst = typst.with_input("width", options.printer.width);
+        if options.preview {
+            let conversion = options.printer.conversion;
+            return Self::preview(conversion, move || Ok(typst.render()?.image)).await;
+        }
+
+        self.submit(options, document, params, |id, tx| {
+            Command::Typst(id, typst, tx)
+        })
+        .await
+    }
+
+    /// Like [`Self::print_typst`], but the text is printed using the printer's
+    /// built-in font if possible. The typst document should display the same
+    /// text. It is used for previews and if the text can't be printed natively.
+    pub async fn print_text(
+        &self,
+        options: JobOptions,
+        document: &str,
+        params: &impl Serialize,
+        text: Text,
+        typst: Typst,
+    ) -> somehow::Result<Response> {
+        let typst = typst.with_input("width", options.printer.width);
+        if options.preview {
+            let conversion = options.printer.conversion;
+            return Self::preview(conversion, move || Ok(typst.render()?.image)).await;
+        }
+
+        self.submit(options, document, params, |id, tx| {
+            Command::Text(id, text, typst, tx)
+        })
+        .await
+    }
+
+    /// Like [`Self::print_typst`], but for an image that has already been
+    /// rendered. The image must be as wide as the printer.
+    pub async fn print_image(
+        &self,
+        options: JobOptions,
+        document: &str,
+        params: &impl Serialize,
+        image: RgbaImage,
+    ) -> somehow::Result<Response> {
+        let width = options.printer.width;
+        if image.width() != width {
+            let info = format!(
+                "Image is {} pixels wide but printer {:?} is {width} pixels wide",
+                image.width(),
+                options.printer.name,
+            );
+            return Ok(status_code_with_info(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &info,
+            ));
+        }
+
+        if options.preview {
+            return Self::preview(options.printer.conversion, move || Ok(image)).await;
+        }
+
+        self.submit(options, document, params, |id, tx| {
+            Command::Image(id, image, tx)
+        })
+        .await
+    }
+
+    /// Respond with an image the way it would be printed.
+    async fn preview(
+        conversion: Conversion,
+        render: impl FnOnce() -> anyhow::Result<RgbaImage> + Send + 'static,
+    ) -> somehow::Result<Response> {
+        let bytes = task::spawn_blocking(move || {
+            let image = Printer::threshold(&render()?, &conversion);
+            let mut bytes: Vec<u8> = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+                .context("failed to encode preview as png")?;
+            anyhow::Ok(bytes)
+        })
+        .await?
+        .map_err(somehow::Error)?;
+
+        Ok(images::png_response(bytes))
     }
 }
 
-pub async fn run(
-    tx: mpsc::Sender<Command>,
-    addr: String,
-    originals: Option<PathBuf>,
-) -> anyhow::Result<()> {
+pub async fn run(server: Server, addr: String) -> anyhow::Result<()> {
     let app = Router::new()
         // Files
         .route("/", get(r#static::get_index))
@@ -45,6 +200,7 @@ pub async fn run(
         // API
         .route("/api/banner", post(documents::banner::post))
         .route("/api/calendar", post(documents::calendar::post))
+        .route("/api/calibration", post(documents::calibration::post))
         .route("/api/catfishing", post(documents::catfishing::post))
         .route("/api/cells", post(documents::cells::post))
         .route("/api/chat", post(documents::chat::post))
@@ -53,15 +209,44 @@ pub async fn run(
             "/api/image",
             post(documents::image::post).get(documents::image::get),
         )
+        .route("/api/plaintext", post(documents::plaintext::post))
         .route("/api/sunrise", post(documents::sunrise::post))
         .route("/api/text", post(documents::text::post))
         .route("/api/tictactoe", post(documents::tictactoe::post))
+        .route("/api/typst", post(documents::typst::post))
         .route("/api/xkcd", post(documents::xkcd::post))
+        // Archive
+        .route("/api/archive", get(archive::get_archive))
+        .route("/api/archive/{id}", get(archive::get_entry))
+        .route("/api/archive/{id}/image", get(archive::get_image))
+        .route("/api/archive/{id}/thumbnail", get(archive::get_thumbnail))
+        .route("/api/archive/{id}/reprint", post(archive::post_reprint))
+        // Jobs
+        .route("/api/jobs", get(jobs::get_jobs))
+        .route("/api/jobs/{id}", get(jobs::get_job))
+        .route("/api/reprint/last", post(jobs::post_reprint_last))
+        .route("/api/reprint/{id}", post(jobs::post_reprint))
+        // Printer
+        .route("/api/printers", get(printer::get_printers))
+        .route("/api/printer", get(printer::get_printer))
+        .route("/api/printer/pause", post(printer::post_pause))
+        .route("/api/printer/resume", post(printer::post_resume))
+        .route("/api/printer/status", get(printer::get_status))
+        // Queue
+        .route(
+            "/api/queue",
+            get(queue::get_queue).delete(queue::delete_queue),
+        )
+        .route("/api/queue/{id}", delete(queue::delete_entry))
+        .route("/api/queue/{id}/image", get(queue::get_image))
+        .route("/api/queue/{id}/thumbnail", get(queue::get_thumbnail))
+        .route("/api/queue/{id}/front", post(queue::post_front))
         // Rest
         .layer(DefaultBodyLimit::max(32 * 1024 * 1024)) // 32 MiB
-        .with_state(Server { tx, originals });
+        .with_state(server);
 
     let listener = TcpListener::bind(addr).await?;
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
     axum::serve(listener, app).await?;
     Ok(())
 }
@@ -0,0 +1,127 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use jiff::civil::Date;
+use serde::Deserialize;
+
+use crate::archive::{Entry, Filter, Record};
+
+use super::{
+    Server,
+    images::{self, ImageEntry},
+    options::JobOptions,
+    somehow,
+    statuscode::status_code,
+};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 1000;
+
+fn entry_info(entry: Entry) -> ImageEntry<Record> {
+    ImageEntry::new("/api/archive", "", entry.id, entry.record)
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveQuery {
+    printer: Option<String>,
+    document: Option<String>,
+    /// Search the document name, submitter and parameters.
+    q: Option<String>,
+    /// First day to include, e.g. `2025-01-31`.
+    from: Option<Date>,
+    /// Last day to include.
+    to: Option<Date>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/// Archived entries matching the query, newest first.
+pub async fn get_archive(
+    server: State<Server>,
+    Query(query): Query<ArchiveQuery>,
+) -> somehow::Result<Response> {
+    let Some(archive) = &server.archive else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+
+    let filter = Filter {
+        printer: query.printer,
+        document: query.document,
+        query: query.q.filter(|it| !it.is_empty()),
+        from: query.from,
+        to: query.to,
+    };
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let entries = archive
+        .entries(&filter, offset, limit)
+        .map_err(somehow::Error)?;
+    let entries = entries.into_iter().map(entry_info).collect::<Vec<_>>();
+    Ok(Json(entries).into_response())
+}
+
+pub async fn get_entry(server: State<Server>, Path(id): Path<String>) -> somehow::Result<Response> {
+    let Some(archive) = &server.archive else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+    if !archive.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    let record = archive.load_record(&id).map_err(somehow::Error)?;
+    Ok(Json(entry_info(Entry { id, record })).into_response())
+}
+
+pub async fn get_image(server: State<Server>, Path(id): Path<String>) -> somehow::Result<Response> {
+    let Some(archive) = &server.archive else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+    if !archive.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    let bytes = archive.load_image_bytes(&id).map_err(somehow::Error)?;
+    Ok(images::png_response(bytes))
+}
+
+pub async fn get_thumbnail(
+    server: State<Server>,
+    Path(id): Path<String>,
+) -> somehow::Result<Response> {
+    let Some(archive) = &server.archive else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+    if !archive.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    let image = archive.load_image(&id).map_err(somehow::Error)?;
+    images::thumbnail_response(&image).map_err(somehow::Error)
+}
+
+/// Print an archived image again as a new job.
+///
+/// The job is routed like any other job, so it may end up on a different
+/// printer than the original, as long as that printer is just as wide.
+pub async fn post_reprint(
+    server: State<Server>,
+    options: JobOptions,
+    Path(id): Path<String>,
+) -> somehow::Result<Response> {
+    let Some(archive) = &server.archive else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+    if !archive.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    let record = archive.load_record(&id).map_err(somehow::Error)?;
+    let image = archive.load_image(&id).map_err(somehow::Error)?;
+    server
+        .print_image(options, &record.document, &record.params, image)
+        .await
+}
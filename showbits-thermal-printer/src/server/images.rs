@@ -0,0 +1,53 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use image::{ImageFormat, RgbaImage, imageops};
+use serde::Serialize;
+
+/// Width of the thumbnails of queued and archived images.
+const THUMBNAIL_WIDTH: u32 = 96;
+
+/// A stored image's metadata along with the URLs of the image itself and its
+/// thumbnail.
+#[derive(Serialize)]
+pub struct ImageEntry<T> {
+    id: String,
+    image: String,
+    thumbnail: String,
+    #[serde(flatten)]
+    meta: T,
+}
+
+impl<T> ImageEntry<T> {
+    /// The image must be served at `{base}/{id}/image` and its thumbnail at
+    /// `{base}/{id}/thumbnail`, both with the same query string.
+    pub fn new(base: &str, query: &str, id: String, meta: T) -> Self {
+        Self {
+            image: format!("{base}/{id}/image{query}"),
+            thumbnail: format!("{base}/{id}/thumbnail{query}"),
+            id,
+            meta,
+        }
+    }
+}
+
+pub fn png_response(bytes: Vec<u8>) -> Response {
+    ([(header::CONTENT_TYPE, "image/png")], bytes).into_response()
+}
+
+pub fn thumbnail_response(image: &RgbaImage) -> anyhow::Result<Response> {
+    let width = THUMBNAIL_WIDTH;
+    let height = (image.height() as u64 * width as u64 / image.width().max(1) as u64) as u32;
+    let thumbnail = imageops::thumbnail(image, width, height.max(1));
+
+    let mut bytes: Vec<u8> = Vec::new();
+    thumbnail
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .context("failed to encode thumbnail as png")?;
+
+    Ok(png_response(bytes))
+}
@@ -0,0 +1,65 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use image::RgbaImage;
+
+use crate::jobs::JobId;
+
+use super::{Server, options::JobOptions, somehow, statuscode::status_code};
+
+pub async fn get_jobs(server: State<Server>) -> impl IntoResponse {
+    Json(server.jobs.list())
+}
+
+pub async fn get_job(server: State<Server>, Path(id): Path<u64>) -> Response {
+    match server.jobs.get(JobId(id)) {
+        None => status_code(StatusCode::NOT_FOUND),
+        Some(job) => Json(job).into_response(),
+    }
+}
+
+async fn reprint(
+    server: &Server,
+    options: JobOptions,
+    id: JobId,
+    image: &RgbaImage,
+) -> somehow::Result<Response> {
+    let (document, params) = match server.jobs.get(id) {
+        Some(job) => (job.document, job.params),
+        None => ("unknown".to_string(), serde_json::Value::Null),
+    };
+    server
+        .print_image(options, &document, &params, image.clone())
+        .await
+}
+
+/// Print the final bitmap of a recent job again, without rendering it anew.
+///
+/// Only the bitmaps of the most recent jobs are kept, and natively printed
+/// text has none.
+pub async fn post_reprint(
+    server: State<Server>,
+    options: JobOptions,
+    Path(id): Path<u64>,
+) -> somehow::Result<Response> {
+    let id = JobId(id);
+    let Some(image) = server.jobs.image(id) else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+    reprint(&server, options, id, &image).await
+}
+
+/// Like [`post_reprint`], but for the most recent job that has a bitmap.
+pub async fn post_reprint_last(
+    server: State<Server>,
+    options: JobOptions,
+) -> somehow::Result<Response> {
+    let Some((id, image)) = server.jobs.last_image() else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+    reprint(&server, options, id, &image).await
+}
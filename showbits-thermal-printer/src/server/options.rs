@@ -0,0 +1,130 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Query},
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    printer::Cut,
+    printers::{ActiveJob, PrinterHandle, Printers},
+};
+
+use super::{Server, statuscode::status_code_with_info};
+
+/// Header that selects a printer, like the `printer` query parameter.
+const PRINTER_HEADER: &str = "printer";
+
+#[derive(Deserialize)]
+struct RawJobOptions {
+    submitter: Option<String>,
+    preview: Option<bool>,
+    cut: Option<Cut>,
+    printer: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawPrinterOptions {
+    printer: Option<String>,
+}
+
+/// The printer named in the query string or header, with the query string
+/// taking precedence.
+fn printer_name(parts: &Parts, query: Option<String>) -> Option<String> {
+    query.or_else(|| {
+        let header = parts.headers.get(PRINTER_HEADER)?;
+        Some(header.to_str().ok()?.to_string())
+    })
+}
+
+fn unknown_printer(name: &str) -> Response {
+    status_code_with_info(StatusCode::NOT_FOUND, &format!("Unknown printer {name:?}"))
+}
+
+/// Options shared by all document routes, taken from the query string.
+pub struct JobOptions {
+    /// Who submitted the job.
+    ///
+    /// Defaults to the client's IP address if not explicitly specified.
+    pub submitter: Option<String>,
+
+    /// Respond with an image of the document instead of printing it.
+    pub preview: bool,
+
+    /// Overrides how the printer cuts the paper after the job.
+    pub cut: Option<Cut>,
+
+    /// The printer the job is sent to.
+    ///
+    /// Defaults to the server's default printer. If that is the pool, or the
+    /// pool is requested explicitly, a printer is picked from the pool.
+    pub printer: PrinterHandle,
+
+    /// The job counts as active from the moment it is routed to the printer,
+    /// so that concurrent requests to the pool are spread across printers.
+    _active: Option<ActiveJob>,
+}
+
+impl FromRequestParts<Server> for JobOptions {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Server,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(raw) = Query::<RawJobOptions>::from_request_parts(parts, state)
+            .await
+            .map_err(|it| it.into_response())?;
+
+        let submitter = raw.submitter.or_else(|| {
+            let ConnectInfo(addr) = parts.extensions.get::<ConnectInfo<SocketAddr>>()?;
+            Some(addr.ip().to_string())
+        });
+
+        let name = printer_name(parts, raw.printer);
+        let name = state.printers.resolve(name.as_deref());
+        let Some(printer) = state.printers.route(name) else {
+            return Err(unknown_printer(name));
+        };
+
+        let preview = raw.preview.unwrap_or(false);
+        let active = (!preview).then(|| printer.start_job());
+
+        Ok(Self {
+            submitter,
+            preview,
+            cut: raw.cut,
+            printer: printer.clone(),
+            _active: active,
+        })
+    }
+}
+
+/// Routes that manage a printer act on the printer named in the query string
+/// or header, or the default printer. The pool can't be managed directly.
+impl FromRequestParts<Server> for PrinterHandle {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Server,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(raw) = Query::<RawPrinterOptions>::from_request_parts(parts, state)
+            .await
+            .map_err(|it| it.into_response())?;
+
+        let name = printer_name(parts, raw.printer);
+        let name = state.printers.resolve(name.as_deref());
+        if name == Printers::POOL {
+            let info = "The pool is not a single printer, select one via the printer parameter";
+            return Err(status_code_with_info(StatusCode::BAD_REQUEST, &info));
+        }
+
+        match state.printers.get(name) {
+            Some(printer) => Ok(printer.clone()),
+            None => Err(unknown_printer(name)),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde::Serialize;
+
+use crate::{drawer::Command, printers::PrinterHandle};
+
+use super::{Server, statuscode::status_code};
+
+#[derive(Serialize)]
+struct PrinterInfo {
+    name: String,
+    width: u32,
+    paused: bool,
+    /// Whether the printer would print a job right away, judging by its most
+    /// recent status.
+    online: bool,
+    active_jobs: usize,
+}
+
+impl From<&PrinterHandle> for PrinterInfo {
+    fn from(printer: &PrinterHandle) -> Self {
+        Self {
+            name: printer.name.clone(),
+            width: printer.width,
+            paused: printer.is_paused(),
+            online: printer.is_online(),
+            active_jobs: printer.active_jobs(),
+        }
+    }
+}
+
+pub async fn get_printers(server: State<Server>) -> impl IntoResponse {
+    let printers = server.printers.iter().map(PrinterInfo::from);
+    Json(printers.collect::<Vec<_>>())
+}
+
+pub async fn get_printer(printer: PrinterHandle) -> impl IntoResponse {
+    Json(PrinterInfo::from(&printer))
+}
+
+/// The printer's status as of the most recent check, or `null` if it hasn't
+/// been checked yet.
+pub async fn get_status(printer: PrinterHandle) -> impl IntoResponse {
+    let report = printer.status.lock().unwrap().clone();
+    Json(report)
+}
+
+pub async fn post_pause(printer: PrinterHandle) -> impl IntoResponse {
+    let _ = printer.tx.send(Command::Pause).await;
+    status_code(StatusCode::OK)
+}
+
+pub async fn post_resume(printer: PrinterHandle) -> impl IntoResponse {
+    let _ = printer.tx.send(Command::Resume).await;
+    status_code(StatusCode::OK)
+}
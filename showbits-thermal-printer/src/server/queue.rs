@@ -0,0 +1,113 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    jobs::{JobState, Jobs},
+    printers::PrinterHandle,
+    queue::{Entry, EntryMeta},
+};
+
+use super::{
+    Server,
+    images::{self, ImageEntry},
+    somehow,
+    statuscode::status_code,
+};
+
+fn entry_info(printer: &PrinterHandle, entry: Entry) -> ImageEntry<EntryMeta> {
+    let query = format!("?printer={}", printer.name);
+    ImageEntry::new("/api/queue", &query, entry.id, entry.meta)
+}
+
+fn mark_removed(jobs: &Jobs, meta: &EntryMeta) {
+    if let Some(job) = meta.job {
+        let error = "Removed from queue".to_string();
+        jobs.set_state(job, JobState::Failed { error });
+    }
+}
+
+pub async fn get_queue(printer: PrinterHandle) -> somehow::Result<impl IntoResponse> {
+    let entries = printer.queue.entries().map_err(somehow::Error)?;
+    let entries = entries
+        .into_iter()
+        .map(|it| entry_info(&printer, it))
+        .collect::<Vec<_>>();
+    Ok(Json(entries))
+}
+
+pub async fn delete_queue(
+    server: State<Server>,
+    printer: PrinterHandle,
+) -> somehow::Result<impl IntoResponse> {
+    let entries = printer.queue.clear().map_err(somehow::Error)?;
+    for entry in &entries {
+        mark_removed(&server.jobs, &entry.meta);
+    }
+    let entries = entries
+        .into_iter()
+        .map(|it| entry_info(&printer, it))
+        .collect::<Vec<_>>();
+    Ok(Json(entries))
+}
+
+pub async fn get_image(
+    printer: PrinterHandle,
+    Path(id): Path<String>,
+) -> somehow::Result<Response> {
+    if !printer.queue.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    let bytes = printer
+        .queue
+        .load_image_bytes(&id)
+        .map_err(somehow::Error)?;
+    Ok(images::png_response(bytes))
+}
+
+pub async fn get_thumbnail(
+    printer: PrinterHandle,
+    Path(id): Path<String>,
+) -> somehow::Result<Response> {
+    if !printer.queue.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    let image = printer.queue.load_image(&id).map_err(somehow::Error)?;
+    images::thumbnail_response(&image).map_err(somehow::Error)
+}
+
+pub async fn delete_entry(
+    server: State<Server>,
+    printer: PrinterHandle,
+    Path(id): Path<String>,
+) -> somehow::Result<Response> {
+    let entries = printer.queue.entries().map_err(somehow::Error)?;
+    let Some(entry) = entries.into_iter().find(|it| it.id == id) else {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    };
+
+    // The entry may have been printed in the meantime.
+    if !printer.queue.remove(&entry.id).map_err(somehow::Error)? {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+    mark_removed(&server.jobs, &entry.meta);
+
+    Ok(Json(entry_info(&printer, entry)).into_response())
+}
+
+pub async fn post_front(
+    printer: PrinterHandle,
+    Path(id): Path<String>,
+) -> somehow::Result<Response> {
+    if !printer.queue.contains(&id) {
+        return Ok(status_code(StatusCode::NOT_FOUND));
+    }
+
+    printer.queue.move_to_front(&id).map_err(somehow::Error)?;
+    Ok(status_code(StatusCode::OK))
+}
@@ -1,14 +1,36 @@
 use std::{error, fmt, result};
 
 use axum::{
+    Json,
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use serde::Serialize;
+
+use crate::jobs::{Job, JobId};
 
 use super::statuscode::status_code_with_info;
 
 pub struct Error(pub anyhow::Error);
 
+/// Context for errors that made a job fail, so that the response can tell
+/// clients which job failed.
+#[derive(Debug)]
+pub struct JobFailed(pub Box<Job>);
+
+impl fmt::Display for JobFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Job {} failed", self.0.id)
+    }
+}
+
+#[derive(Serialize)]
+struct TypstError<'a> {
+    job: Option<JobId>,
+    #[serde(flatten)]
+    error: &'a showbits_typst::Error,
+}
+
 impl<E> From<E> for Error
 where
     E: error::Error + Send + Sync + 'static,
@@ -32,6 +54,21 @@ impl fmt::Display for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
+        let job = self.0.downcast_ref::<JobFailed>().map(|it| &it.0);
+
+        // Typst errors carry enough structure that clients can show exactly
+        // where in the document things went wrong.
+        if let Some(error) = self.0.downcast_ref::<showbits_typst::Error>() {
+            let job = job.map(|it| it.id);
+            let body = TypstError { job, error };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response();
+        }
+
+        // The job contains the error, see [`crate::jobs::JobState::Failed`].
+        if let Some(job) = job {
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(job)).into_response();
+        }
+
         status_code_with_info(StatusCode::INTERNAL_SERVER_ERROR, &self.0)
     }
 }
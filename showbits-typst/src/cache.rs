@@ -0,0 +1,40 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// Values that are kept around for as long as they keep being used.
+///
+/// Like typst's memoized results (see [`comemo::evict`]), each value remembers
+/// how many evictions ago it was last used. Values that are only used once,
+/// e.g. the files of a single request, are dropped again after a while instead
+/// of piling up.
+pub struct Cache<K, V> {
+    entries: HashMap<K, (V, usize)>,
+}
+
+impl<K: Eq + Hash, V: Clone> Cache<K, V> {
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let (value, age) = self.entries.get_mut(key)?;
+        *age = 0;
+        Some(value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.entries.insert(key, (value, 0));
+    }
+
+    /// Remove all values that haven't been used for more than `max_age`
+    /// evictions.
+    pub fn evict(&mut self, max_age: usize) {
+        self.entries.retain(|_, (_, age)| {
+            *age += 1;
+            *age <= max_age
+        });
+    }
+}
+
+impl<K, V> Default for Cache<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{error, fmt, ops::Range};
+
+use serde::Serialize;
+use typst::{
+    World,
+    diag::{self, SourceDiagnostic},
+    syntax::Span,
+};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A compiler error or warning along with its location in the source files.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>,
+    /// Byte range within the file.
+    pub range: Option<Range<usize>>,
+    /// One-based line number.
+    pub line: Option<usize>,
+    /// One-based column number.
+    pub column: Option<usize>,
+    pub message: String,
+    pub hints: Vec<String>,
+}
+
+struct Location {
+    file: String,
+    range: Range<usize>,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn of(world: &dyn World, span: Span) -> Option<Self> {
+        let id = span.id()?;
+        let source = world.source(id).ok()?;
+        let range = source.range(span)?;
+        let line = source.byte_to_line(range.start)?;
+        let column = source.byte_to_column(range.start)?;
+
+        let mut file = id.vpath().as_rooted_path().display().to_string();
+        if let Some(spec) = id.package() {
+            file = format!("{spec}{file}");
+        }
+
+        Some(Self {
+            file,
+            range,
+            line: line + 1,
+            column: column + 1,
+        })
+    }
+}
+
+impl Diagnostic {
+    pub(crate) fn new(world: &dyn World, diagnostic: &SourceDiagnostic) -> Self {
+        let severity = match diagnostic.severity {
+            diag::Severity::Error => Severity::Error,
+            diag::Severity::Warning => Severity::Warning,
+        };
+
+        let location = Location::of(world, diagnostic.span);
+
+        Self {
+            severity,
+            file: location.as_ref().map(|it| it.file.clone()),
+            range: location.as_ref().map(|it| it.range.clone()),
+            line: location.as_ref().map(|it| it.line),
+            column: location.as_ref().map(|it| it.column),
+            message: diagnostic.message.to_string(),
+            hints: diagnostic.hints.iter().map(|it| it.to_string()).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(file), Some(line), Some(column)) = (&self.file, self.line, self.column) {
+            write!(f, "{file}:{line}:{column}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Error {
+    /// The document failed to compile.
+    Compile {
+        errors: Vec<Diagnostic>,
+        warnings: Vec<Diagnostic>,
+    },
+    /// The compiled document could not be converted into an image.
+    Image,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile { errors, .. } => {
+                let msg = errors
+                    .iter()
+                    .map(|it| it.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                write!(f, "{msg}")
+            }
+            Self::Image => write!(f, "Failed to create image from raw pixel data"),
+        }
+    }
+}
+
+impl error::Error for Error {}
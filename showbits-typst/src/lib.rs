@@ -1,17 +1,20 @@
+mod cache;
+mod error;
+
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
     fs,
     path::{Path, PathBuf},
-    sync::OnceLock,
+    sync::{Arc, LazyLock, Mutex, OnceLock},
 };
 
-use anyhow::anyhow;
 use image::RgbaImage;
 use serde::Serialize;
 use typst::{
     Library, World,
-    diag::{FileError, FileResult},
-    foundations::{Bytes, Datetime},
+    diag::{FileError, FileResult, PackageError},
+    foundations::{Bytes, Datetime, Dict, IntoValue},
     layout::{Abs, PagedDocument},
     syntax::{FileId, Source, VirtualPath, package::PackageSpec},
     text::{Font, FontBook},
@@ -23,6 +26,71 @@ use typst_kit::{
     package::PackageStorage,
 };
 
+use self::cache::Cache;
+
+pub use self::error::{Diagnostic, Error, Severity};
+
+/// Looks up a file of a package embedded into the binary.
+///
+/// The path is relative to a package directory and starts with the package's
+/// namespace, name and version, for example `preview/cetz/0.3.2/src/lib.typ`.
+pub type EmbeddedPackages = fn(&str) -> Option<Cow<'static, [u8]>>;
+
+/// The contents of a file added to a document.
+///
+/// Static data, e.g. from [`include_str!`], is the same in every compilation, so
+/// typst can reuse the parsed file and its memoized results from previous
+/// compilations. Owned data, e.g. from a request, is loaded anew every time.
+pub struct FileData(Cow<'static, [u8]>);
+
+impl From<&'static str> for FileData {
+    fn from(value: &'static str) -> Self {
+        Self(Cow::Borrowed(value.as_bytes()))
+    }
+}
+
+impl From<&'static [u8]> for FileData {
+    fn from(value: &'static [u8]) -> Self {
+        Self(Cow::Borrowed(value))
+    }
+}
+
+impl<const N: usize> From<&'static [u8; N]> for FileData {
+    fn from(value: &'static [u8; N]) -> Self {
+        Self(Cow::Borrowed(value))
+    }
+}
+
+impl From<String> for FileData {
+    fn from(value: String) -> Self {
+        Self(Cow::Owned(value.into_bytes()))
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(value: Vec<u8>) -> Self {
+        Self(Cow::Owned(value))
+    }
+}
+
+/// Identifies a file whose contents are the same in every compilation.
+///
+/// Published package versions never change. Static files of documents are told
+/// apart by the address of their data, since different documents use the same
+/// paths (e.g. `/main.typ`) for different files. Unlike hashing the contents,
+/// this is free.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum StaticFile {
+    Package(FileId),
+    Document(FileId, usize, usize),
+}
+
+/// A successfully rendered document.
+pub struct Rendered {
+    pub image: RgbaImage,
+    pub warnings: Vec<Diagnostic>,
+}
+
 // The logic for detecting and loading fonts was ripped straight from:
 // https://github.com/typst/typst/blob/69dcc89d84176838c293b2d59747cd65e28843ad/crates/typst-cli/src/fonts.rs
 // https://github.com/typst/typst/blob/69dcc89d84176838c293b2d59747cd65e28843ad/crates/typst-cli/src/world.rs#L193-L195
@@ -84,27 +152,125 @@ impl FontLoader {
     }
 }
 
-pub struct Typst {
-    library: LazyHash<Library>,
+/// The parts of a typst world that are expensive to create but rarely change.
+struct Context {
+    libraries: Mutex<Cache<BTreeMap<String, String>, Arc<LazyHash<Library>>>>,
     book: LazyHash<FontBook>,
     fonts: Vec<FontSlot>,
-    files: HashMap<String, Vec<u8>>,
-    packages: PackageStorage,
+    sources: Mutex<Cache<StaticFile, Source>>,
+    files: Mutex<Cache<StaticFile, Bytes>>,
 }
 
-impl Typst {
-    const MAIN_PATH: &str = "/main.typ";
-
-    pub fn new() -> Self {
+impl Context {
+    fn new() -> Self {
         let mut loader = FontLoader::new();
         loader.load_embedded_fonts();
         loader.load_unifonts();
 
         Self {
-            library: LazyHash::new(Library::default()),
+            libraries: Mutex::new(Cache::default()),
             book: LazyHash::new(loader.book),
             fonts: loader.fonts,
+            sources: Mutex::new(Cache::default()),
+            files: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Loading all fonts takes a while, so the context is created once and
+    /// then shared between all [`Typst`] instances.
+    fn shared() -> Arc<Self> {
+        static CONTEXT: LazyLock<Arc<Context>> = LazyLock::new(|| Arc::new(Context::new()));
+        CONTEXT.clone()
+    }
+
+    /// The standard library with the given `sys.inputs`.
+    ///
+    /// Libraries are reused for the same reasons as sources and files.
+    fn library(&self, inputs: &BTreeMap<String, String>) -> Arc<LazyHash<Library>> {
+        let mut libraries = self.libraries.lock().unwrap();
+        if let Some(library) = libraries.get(inputs) {
+            return library;
+        }
+
+        let dict = inputs
+            .iter()
+            .map(|(key, value)| (key.as_str().into(), value.as_str().into_value()))
+            .collect::<Dict>();
+        let library = Arc::new(LazyHash::new(Library::builder().with_inputs(dict).build()));
+        libraries.insert(inputs.clone(), library.clone());
+        library
+    }
+
+    // Static files (e.g. the library or a document's main file) are identical
+    // between compilations. Handing typst the same source and bytes objects as
+    // last time means it doesn't have to parse and hash them again, and it can
+    // reuse most of its memoized results from the previous compilations.
+    //
+    // The caches aren't locked while loading, so that e.g. downloading a
+    // package doesn't block other compilations.
+
+    fn source(
+        &self,
+        key: StaticFile,
+        load: impl FnOnce() -> FileResult<Source>,
+    ) -> FileResult<Source> {
+        if let Some(source) = self.sources.lock().unwrap().get(&key) {
+            return Ok(source);
+        }
+        let source = load()?;
+        self.sources.lock().unwrap().insert(key, source.clone());
+        Ok(source)
+    }
+
+    fn file(&self, key: StaticFile, load: impl FnOnce() -> FileResult<Bytes>) -> FileResult<Bytes> {
+        if let Some(file) = self.files.lock().unwrap().get(&key) {
+            return Ok(file);
+        }
+        let file = load()?;
+        self.files.lock().unwrap().insert(key, file.clone());
+        Ok(file)
+    }
+
+    /// Drop everything that hasn't been used for more than `max_age`
+    /// evictions.
+    fn evict(&self, max_age: usize) {
+        self.libraries.lock().unwrap().evict(max_age);
+        self.sources.lock().unwrap().evict(max_age);
+        self.files.lock().unwrap().evict(max_age);
+    }
+}
+
+pub struct Typst {
+    context: Arc<Context>,
+    files: HashMap<String, FileData>,
+    /// Sources and files already loaded for this document, so typst gets the
+    /// same objects every time it asks for them.
+    sources: Mutex<HashMap<FileId, Source>>,
+    bytes: Mutex<HashMap<FileId, Bytes>>,
+    embedded_packages: Option<EmbeddedPackages>,
+    package_dir: Option<PathBuf>,
+    network: bool,
+    packages: PackageStorage,
+    inputs: BTreeMap<String, String>,
+    library: OnceLock<Arc<LazyHash<Library>>>,
+}
+
+impl Typst {
+    const MAIN_PATH: &str = "/main.typ";
+
+    /// How many evictions memoized results, sources and files are kept around
+    /// for after they were last used.
+    const CACHE_MAX_AGE: usize = 32;
+
+    pub fn new() -> Self {
+        Self {
+            context: Context::shared(),
             files: HashMap::new(),
+            sources: Mutex::new(HashMap::new()),
+            bytes: Mutex::new(HashMap::new()),
+            embedded_packages: None,
+            package_dir: None,
+            network: false,
             packages: PackageStorage::new(
                 None,
                 None,
@@ -113,20 +279,73 @@ impl Typst {
                     env!("CARGO_PKG_VERSION")
                 )),
             ),
+            inputs: BTreeMap::new(),
+            library: OnceLock::new(),
         }
     }
 
-    pub fn add_file(&mut self, path: impl ToString, data: impl Into<Vec<u8>>) {
+    pub fn add_file(&mut self, path: impl ToString, data: impl Into<FileData>) {
         let path = path.to_string();
         let data = data.into();
         self.files.insert(path, data);
+        self.sources.get_mut().unwrap().clear();
+        self.bytes.get_mut().unwrap().clear();
     }
 
-    pub fn with_file(mut self, path: impl ToString, data: impl Into<Vec<u8>>) -> Self {
+    pub fn with_file(mut self, path: impl ToString, data: impl Into<FileData>) -> Self {
         self.add_file(path, data);
         self
     }
 
+    /// Use packages embedded into the binary.
+    ///
+    /// Files are only looked up when a document uses them. Embedded packages
+    /// take precedence over all other package sources.
+    pub fn set_embedded_packages(&mut self, packages: Option<EmbeddedPackages>) {
+        self.embedded_packages = packages;
+    }
+
+    pub fn with_embedded_packages(mut self, packages: Option<EmbeddedPackages>) -> Self {
+        self.set_embedded_packages(packages);
+        self
+    }
+
+    /// Look for packages in a local directory.
+    ///
+    /// The directory has the same layout as typst's own package directories,
+    /// i.e. packages are located at `{namespace}/{name}/{version}`.
+    pub fn set_package_dir(&mut self, dir: Option<PathBuf>) {
+        self.package_dir = dir;
+    }
+
+    pub fn with_package_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.set_package_dir(dir);
+        self
+    }
+
+    /// Allow downloading packages that can't be found locally.
+    ///
+    /// Disabled by default.
+    pub fn set_network(&mut self, network: bool) {
+        self.network = network;
+    }
+
+    pub fn with_network(mut self, network: bool) -> Self {
+        self.set_network(network);
+        self
+    }
+
+    /// Make a value available to the document as `sys.inputs.at(key)`.
+    pub fn add_input(&mut self, key: impl ToString, value: impl ToString) {
+        self.inputs.insert(key.to_string(), value.to_string());
+        self.library = OnceLock::new();
+    }
+
+    pub fn with_input(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.add_input(key, value);
+        self
+    }
+
     pub fn add_json<T: Serialize>(&mut self, path: impl ToString, data: &T) {
         let data = serde_json::to_vec(data).expect("data should serialize to json");
         self.add_file(path, data);
@@ -137,34 +356,50 @@ impl Typst {
         self
     }
 
-    pub fn add_main_file(&mut self, data: impl Into<Vec<u8>>) {
+    pub fn add_main_file(&mut self, data: impl Into<FileData>) {
         self.add_file(Self::MAIN_PATH, data);
     }
 
-    pub fn with_main_file(mut self, data: impl Into<Vec<u8>>) -> Self {
+    pub fn with_main_file(mut self, data: impl Into<FileData>) -> Self {
         self.add_main_file(data);
         self
     }
 
-    pub fn render(&self) -> anyhow::Result<RgbaImage> {
-        let document = typst::compile::<PagedDocument>(self)
-            .output
-            .map_err(|err| {
-                let msg = err
-                    .into_iter()
-                    .map(|it| it.message.to_string())
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                anyhow!("{msg}")
-            })?;
+    /// Render the document into a single image.
+    pub fn render(&self) -> Result<Rendered, Error> {
+        let result = typst::compile::<PagedDocument>(self);
+
+        let warnings = result
+            .warnings
+            .iter()
+            .map(|it| Diagnostic::new(self, it))
+            .collect::<Vec<_>>();
+
+        let document = result.output.map_err(|err| Error::Compile {
+            errors: err.iter().map(|it| Diagnostic::new(self, it)).collect(),
+            warnings: warnings.clone(),
+        })?;
 
         let pixmap = typst_render::render_merged(&document, 1.0, Abs::zero(), Some(Color::WHITE));
 
-        RgbaImage::from_raw(pixmap.width(), pixmap.height(), pixmap.take())
-            .ok_or(anyhow!("Failed to create image from raw pixel data"))
+        let image = RgbaImage::from_raw(pixmap.width(), pixmap.height(), pixmap.take())
+            .ok_or(Error::Image)?;
+
+        Ok(Rendered { image, warnings })
+    }
+
+    /// Drop memoized results, sources and files that haven't been used for a
+    /// while.
+    ///
+    /// The caches are shared by all documents, so this should be called
+    /// regularly, but not after every single compilation, e.g. whenever a batch
+    /// of documents has been rendered.
+    pub fn evict_caches() {
+        comemo::evict(Self::CACHE_MAX_AGE);
+        Context::shared().evict(Self::CACHE_MAX_AGE);
     }
 
-    fn get_file_bytes(&self, path: &Path) -> FileResult<&[u8]> {
+    fn get_file_bytes(&self, path: &Path) -> FileResult<&FileData> {
         let path_str = path
             .to_str()
             .ok_or_else(|| FileError::NotFound(path.to_path_buf()))?;
@@ -177,17 +412,80 @@ impl Typst {
         Ok(bytes)
     }
 
+    fn read_package_file(dir: &Path, vpath: &VirtualPath) -> FileResult<Cow<'static, [u8]>> {
+        let path = vpath.resolve(dir).ok_or(FileError::AccessDenied)?;
+        if path.is_dir() {
+            Err(FileError::IsDirectory)?;
+        }
+        let bytes = fs::read(&path).map_err(|it| FileError::from_io(it, &path))?;
+        Ok(Cow::Owned(bytes))
+    }
+
+    /// Where to cache a file between compilations, if its contents are the same
+    /// in every compilation.
+    fn static_file(&self, id: FileId) -> Option<StaticFile> {
+        if id.package().is_some() {
+            return Some(StaticFile::Package(id));
+        }
+        match self.get_file_bytes(id.vpath().as_rooted_path()).ok()? {
+            FileData(Cow::Borrowed(data)) => {
+                Some(StaticFile::Document(id, data.as_ptr() as usize, data.len()))
+            }
+            FileData(Cow::Owned(_)) => None,
+        }
+    }
+
+    fn read_file(&self, id: FileId) -> FileResult<Cow<'static, [u8]>> {
+        if let Some(spec) = id.package() {
+            return self.get_package_file_bytes(spec, id.vpath());
+        }
+        let path = id.vpath().as_rooted_path();
+        Ok(self.get_file_bytes(path)?.0.clone())
+    }
+
+    fn load_source(&self, id: FileId) -> FileResult<Source> {
+        let bytes = self.read_file(id)?;
+        let text = String::from_utf8(bytes.into_owned()).map_err(|_| FileError::InvalidUtf8)?;
+        Ok(Source::new(id, text))
+    }
+
+    fn load_file(&self, id: FileId) -> FileResult<Bytes> {
+        Ok(Bytes::new(self.read_file(id)?))
+    }
+
     fn get_package_file_bytes(
         &self,
         spec: &PackageSpec,
         vpath: &'static VirtualPath,
-    ) -> FileResult<Vec<u8>> {
-        let dir = self.packages.prepare_package(spec, &mut ProgressSink)?;
-        let path = vpath.resolve(&dir).ok_or(FileError::AccessDenied)?;
-        if path.is_dir() {
-            Err(FileError::IsDirectory)?;
+    ) -> FileResult<Cow<'static, [u8]>> {
+        let package = format!("{}/{}/{}", spec.namespace, spec.name, spec.version);
+
+        // Every package has a manifest, so it tells whether the package is
+        // embedded without having to list all embedded files.
+        if let Some(embedded) = self.embedded_packages
+            && embedded(&format!("{package}/typst.toml")).is_some()
+        {
+            let path = format!("{package}{}", vpath.as_rooted_path().display());
+            let bytes = embedded(&path)
+                .ok_or_else(|| FileError::NotFound(vpath.as_rooted_path().to_path_buf()))?;
+            return Ok(bytes);
+        }
+
+        if let Some(dir) = &self.package_dir {
+            let dir = dir.join(&package);
+            if dir.is_dir() {
+                return Self::read_package_file(&dir, vpath);
+            }
         }
-        fs::read(&path).map_err(|it| FileError::from_io(it, &path))
+
+        if !self.network {
+            let msg =
+                format!("package {spec} is not available locally and network access is disabled");
+            Err(PackageError::Other(Some(msg.into())))?;
+        }
+
+        let dir = self.packages.prepare_package(spec, &mut ProgressSink)?;
+        Self::read_package_file(&dir, vpath)
     }
 }
 
@@ -199,15 +497,16 @@ impl Default for Typst {
 
 impl World for Typst {
     fn library(&self) -> &LazyHash<Library> {
-        &self.library
+        self.library
+            .get_or_init(|| self.context.library(&self.inputs))
     }
 
     fn book(&self) -> &LazyHash<FontBook> {
-        &self.book
+        &self.context.book
     }
 
     fn font(&self, index: usize) -> Option<Font> {
-        self.fonts.get(index)?.get()
+        self.context.fonts.get(index)?.get()
     }
 
     fn main(&self) -> FileId {
@@ -217,28 +516,31 @@ impl World for Typst {
     fn source(&self, id: FileId) -> FileResult<Source> {
         println!("Accessing source {id:?}");
 
-        let bytes = if let Some(spec) = id.package() {
-            self.get_package_file_bytes(spec, id.vpath())?
-        } else {
-            let path = id.vpath().as_rooted_path();
-            self.get_file_bytes(path)?.to_vec()
-        };
+        if let Some(source) = self.sources.lock().unwrap().get(&id) {
+            return Ok(source.clone());
+        }
 
-        let text = String::from_utf8(bytes).map_err(|_| FileError::InvalidUtf8)?;
-        Ok(Source::new(id, text))
+        let source = match self.static_file(id) {
+            Some(key) => self.context.source(key, || self.load_source(id))?,
+            None => self.load_source(id)?,
+        };
+        self.sources.lock().unwrap().insert(id, source.clone());
+        Ok(source)
     }
 
     fn file(&self, id: FileId) -> FileResult<Bytes> {
         println!("Accessing file {id:?}");
 
-        let bytes = if let Some(spec) = id.package() {
-            self.get_package_file_bytes(spec, id.vpath())?
-        } else {
-            let path = id.vpath().as_rooted_path();
-            self.get_file_bytes(path)?.to_vec()
-        };
+        if let Some(file) = self.bytes.lock().unwrap().get(&id) {
+            return Ok(file.clone());
+        }
 
-        Ok(Bytes::new(bytes))
+        let file = match self.static_file(id) {
+            Some(key) => self.context.file(key, || self.load_file(id))?,
+            None => self.load_file(id)?,
+        };
+        self.bytes.lock().unwrap().insert(id, file.clone());
+        Ok(file)
     }
 
     fn today(&self, _offset: Option<i64>) -> Option<Datetime> {